@@ -0,0 +1,58 @@
+// Volume envelope of the pulse and noise channels, driven by NRx2:
+// initial volume (bits 7-4), direction (bit 3, 1 = up) and period (bits 2-0).
+// Clocked at 64 Hz, a period of 0 stops it.
+pub struct Envelope {
+    initial_volume: u8,
+    increase: bool,
+    period: u8,
+    timer: u8,
+    volume: u8,
+}
+
+impl Envelope {
+    pub fn new() -> Self {
+        Envelope { initial_volume: 0, increase: false, period: 0, timer: 0, volume: 0 }
+    }
+
+    // Takes effect on the next trigger
+    pub fn write(&mut self, value: u8) {
+        self.initial_volume = value >> 4;
+        self.increase = value & 0b0000_1000 != 0;
+        self.period = value & 0b0000_0111;
+    }
+
+    pub fn volume(&self) -> u8 {
+        self.volume
+    }
+
+    // `delayed` is set when the next frame sequencer step clocks the envelope,
+    // which then runs one clock later than usual
+    pub fn trigger(&mut self, delayed: bool) {
+        self.timer = if self.period == 0 { 8 } else { self.period };
+        if delayed {
+            self.timer += 1;
+        }
+        self.volume = self.initial_volume;
+    }
+
+    pub fn clock(&mut self) {
+        if self.period == 0 {
+            return;
+        }
+        self.timer = self.timer.saturating_sub(1);
+        if self.timer > 0 {
+            return;
+        }
+        self.timer = self.period;
+        if self.increase && self.volume < 15 {
+            self.volume += 1;
+        } else if !self.increase && self.volume > 0 {
+            self.volume -= 1;
+        }
+    }
+}
+
+// The DAC is on while any of the upper five bits of NRx2 are set
+pub fn dac_enabled(nrx2: u8) -> bool {
+    nrx2 & 0xF8 != 0
+}
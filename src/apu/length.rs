@@ -0,0 +1,46 @@
+// Length counter shared by all four channels. Counts down at 256 Hz while
+// enabled and switches the channel off when it reaches zero.
+pub struct LengthCounter {
+    counter: u16,
+    enabled: bool,
+    max: u16, // 64, or 256 for the wave channel
+}
+
+impl LengthCounter {
+    pub fn new(max: u16) -> Self {
+        LengthCounter { counter: 0, enabled: false, max }
+    }
+
+    // NRx1 holds the length as `max - counter`
+    pub fn load(&mut self, length: u8) {
+        self.counter = self.max - (length as u16 & (self.max - 1));
+    }
+
+    // Frame sequencer clock, returns true when the channel has to be switched off
+    pub fn clock(&mut self) -> bool {
+        if self.enabled && self.counter > 0 {
+            self.counter -= 1;
+            return self.counter == 0;
+        }
+        false
+    }
+
+    // NRx4 bit 6. Enabling the counter while the next frame sequencer step doesn't
+    // clock length still clocks it once. Returns true when that extra clock ran out.
+    pub fn set_enabled(&mut self, enabled: bool, extra_clock: bool) -> bool {
+        let was_enabled = self.enabled;
+        self.enabled = enabled;
+        !was_enabled && enabled && extra_clock && self.clock()
+    }
+
+    // A trigger with the counter at zero reloads it with the maximum, less the
+    // extra clock from above if it applies
+    pub fn trigger(&mut self, extra_clock: bool) {
+        if self.counter == 0 {
+            self.counter = self.max;
+            if self.enabled && extra_clock {
+                self.counter -= 1;
+            }
+        }
+    }
+}
@@ -0,0 +1,188 @@
+// Cycles per second of the APU clock
+pub const CLOCK_RATE: u32 = 4_194_304;
+
+// Per cycle charge factor of the capacitor behind the output on each model
+const DMG_CHARGE_FACTOR: f64 = 0.999958;
+const CGB_CHARGE_FACTOR: f64 = 0.998943;
+
+// Turns the analog channel mix into host rate stereo samples. Each output
+// sample is the average of the mix over the cycles it covers, then passes the
+// high-pass filter formed by the output capacitor.
+pub struct Mixer {
+    sample_rate: u32,
+    cycles_per_sample: f64,
+    pending_cycles: f64,
+    sum: [f32; 2],
+    summed_cycles: u32,
+    charge_factor: f32,
+    capacitor: [f32; 2],
+    samples: Vec<f32>, // interleaved left, right in -1.0..=1.0
+    // Each channel's DAC output on its own, before panning, volume and filtering
+    channel_sum: [f32; 4],
+    channel_samples: Option<[Vec<f32>; 4]>,
+}
+
+impl Mixer {
+    pub fn new(sample_rate: u32, cgb: bool) -> Self {
+        let mut mixer = Mixer {
+            sample_rate,
+            cycles_per_sample: CLOCK_RATE as f64 / sample_rate as f64,
+            pending_cycles: 0.0,
+            sum: [0.0; 2],
+            summed_cycles: 0,
+            charge_factor: 0.0,
+            capacitor: [0.0; 2],
+            samples: Vec::new(),
+            channel_sum: [0.0; 4],
+            channel_samples: None,
+        };
+        mixer.set_cgb(cgb);
+        mixer
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn set_cgb(&mut self, cgb: bool) {
+        let factor = if cgb { CGB_CHARGE_FACTOR } else { DMG_CHARGE_FACTOR };
+        self.charge_factor = factor.powf(self.cycles_per_sample) as f32;
+    }
+
+    pub fn set_channel_capture(&mut self, enabled: bool) {
+        self.channel_samples = enabled.then(Default::default);
+    }
+
+    // Adds `cycles` cycles of the given mix. `dacs_on` is false while every
+    // channel DAC is off, which lets the capacitor discharge.
+    pub fn mix(&mut self, cycles: u32, left: f32, right: f32, dacs_on: bool, channels: [f32; 4]) {
+        self.sum[0] += left * cycles as f32;
+        self.sum[1] += right * cycles as f32;
+        for (sum, output) in self.channel_sum.iter_mut().zip(channels) {
+            *sum += output * cycles as f32;
+        }
+        self.summed_cycles += cycles;
+        self.pending_cycles += cycles as f64;
+
+        // Rates above one sample per chunk repeat the current mix
+        while self.pending_cycles >= self.cycles_per_sample {
+            self.pending_cycles -= self.cycles_per_sample;
+            let (stereo, channels) = if self.summed_cycles == 0 {
+                ([left, right], channels)
+            } else {
+                let summed = self.summed_cycles as f32;
+                (self.sum.map(|sum| sum / summed), self.channel_sum.map(|sum| sum / summed))
+            };
+            self.push_sample(stereo, channels, dacs_on);
+            self.sum = [0.0; 2];
+            self.channel_sum = [0.0; 4];
+            self.summed_cycles = 0;
+        }
+    }
+
+    fn push_sample(&mut self, stereo: [f32; 2], channels: [f32; 4], dacs_on: bool) {
+        for (input, capacitor) in stereo.into_iter().zip(&mut self.capacitor) {
+            let output = if dacs_on {
+                let output = input - *capacitor;
+                *capacitor = input - output * self.charge_factor;
+                output
+            } else {
+                0.0
+            };
+            self.samples.push(output.clamp(-1.0, 1.0));
+        }
+        if let Some(channel_samples) = &mut self.channel_samples {
+            for (samples, output) in channel_samples.iter_mut().zip(channels) {
+                samples.push(output);
+            }
+        }
+    }
+
+    pub fn take_samples(&mut self) -> Vec<f32> {
+        std::mem::take(&mut self.samples)
+    }
+
+    // Mono samples of channels 1-4, empty unless channel capture is on
+    pub fn take_channel_samples(&mut self) -> [Vec<f32>; 4] {
+        match &mut self.channel_samples {
+            Some(channel_samples) => std::mem::take(channel_samples),
+            None => Default::default(),
+        }
+    }
+}
+
+// Analog output of a channel DAC, -1.0..=1.0, or silence while it's off
+pub fn dac_output(digital: u8, dac_enabled: bool) -> f32 {
+    if dac_enabled { digital as f32 / 7.5 - 1.0 } else { 0.0 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Mixes one second of a constant signal in 4 cycle chunks
+    fn one_second(mixer: &mut Mixer, level: f32) {
+        for _ in 0..CLOCK_RATE / 4 {
+            mixer.mix(4, level, -level, true, [level; 4]);
+        }
+    }
+
+    #[test]
+    fn sample_count_matches_the_rate() {
+        for rate in [8000, 44100, 48000, 1_048_576, 2_000_000, 4_000_000] {
+            let mut mixer = Mixer::new(rate, false);
+            one_second(&mut mixer, 0.5);
+            let frames = mixer.take_samples().len() / 2;
+            assert!(frames.abs_diff(rate as usize) <= 1, "{} Hz gave {} frames", rate, frames);
+        }
+    }
+
+    #[test]
+    fn channel_capture_keeps_pace_with_the_mix() {
+        let mut mixer = Mixer::new(3_000_000, false);
+        mixer.set_channel_capture(true);
+        one_second(&mut mixer, 0.25);
+        let frames = mixer.take_samples().len() / 2;
+        let channels = mixer.take_channel_samples();
+        for samples in &channels {
+            assert_eq!(samples.len(), frames);
+            assert!(samples.iter().all(|&sample| sample == 0.25));
+        }
+    }
+
+    #[test]
+    fn averages_over_each_sample() {
+        // One sample per 8 cycles, half at 1.0 and half at 0.0
+        let mut mixer = Mixer::new(CLOCK_RATE / 8, false);
+        mixer.set_channel_capture(true);
+        mixer.mix(4, 1.0, 1.0, true, [1.0; 4]);
+        mixer.mix(4, 0.0, 0.0, true, [0.0; 4]);
+        assert_eq!(mixer.take_channel_samples()[0], [0.5]);
+    }
+
+    #[test]
+    fn high_pass_removes_dc() {
+        let mut mixer = Mixer::new(44100, true);
+        one_second(&mut mixer, 0.8);
+        let samples = mixer.take_samples();
+        assert!((samples[0] - 0.8).abs() < 0.05);
+        assert!(samples[samples.len() - 2].abs() < 0.01);
+        assert!((samples[samples.len() - 1]).abs() < 0.01);
+    }
+
+    #[test]
+    fn silent_while_dacs_are_off() {
+        let mut mixer = Mixer::new(44100, false);
+        for _ in 0..1000 {
+            mixer.mix(4, 0.7, 0.7, false, [0.0; 4]);
+        }
+        assert!(mixer.take_samples().iter().all(|&sample| sample == 0.0));
+    }
+
+    #[test]
+    fn dac_output_range() {
+        assert_eq!(dac_output(0, true), -1.0);
+        assert_eq!(dac_output(15, true), 1.0);
+        assert_eq!(dac_output(15, false), 0.0);
+    }
+}
@@ -0,0 +1,355 @@
+pub mod envelope;
+pub mod length;
+pub mod pulse;
+pub mod wave;
+pub mod noise;
+pub mod mixer;
+
+use pulse::PulseChannel;
+use wave::WaveChannel;
+use noise::NoiseChannel;
+use mixer::Mixer;
+
+use crate::data::HardwareRegister;
+
+// NR10-NR52 and wave RAM, 0xFF10-0xFF3F
+pub const APU_START: u16 = 0xFF10;
+pub const APU_END: u16 = 0xFF3F;
+const WAVE_RAM_START: u16 = HardwareRegister::WaveRAM as u16;
+const REGISTER_COUNT: usize = (WAVE_RAM_START - APU_START) as usize;
+
+const NR52_POWER: u8 = 0b1000_0000;
+
+// The channels are mixed once per M-cycle
+const MIX_CYCLES: u16 = 4;
+
+// DIV bit whose falling edge clocks the frame sequencer (512 Hz)
+const DIV_FRAME_SEQUENCER_BIT: u8 = 0b0001_0000;
+
+// Audio processing unit. Lives on the memory bus since most of its registers
+// have side effects on write.
+//
+// The frame sequencer runs 8 steps at 512 Hz:
+//   step  0 1 2 3 4 5 6 7
+//   len   x   x   x   x        256 Hz
+//   sweep     x       x        128 Hz
+//   env                 x       64 Hz
+pub struct Apu {
+    registers: [u8; REGISTER_COUNT], // NR10-NR52, wave RAM belongs to channel 3
+    powered: bool,
+    cgb: bool,
+    frame_step: u8, // next step the frame sequencer runs
+    div_bit: bool,
+    pub channel1: PulseChannel,
+    pub channel2: PulseChannel,
+    pub channel3: WaveChannel,
+    pub channel4: NoiseChannel,
+    mixer: Option<Mixer>,
+}
+
+impl Apu {
+    pub fn new() -> Self {
+        Apu {
+            registers: [0; REGISTER_COUNT],
+            powered: false,
+            cgb: false,
+            frame_step: 0,
+            div_bit: false,
+            channel1: PulseChannel::new(true),
+            channel2: PulseChannel::new(false),
+            channel3: WaveChannel::new(),
+            channel4: NoiseChannel::new(),
+            mixer: None,
+        }
+    }
+
+    pub fn set_cgb(&mut self, cgb: bool) {
+        self.cgb = cgb;
+        if let Some(mixer) = &mut self.mixer {
+            mixer.set_cgb(cgb);
+        }
+    }
+
+    // Starts mixing samples at `sample_rate` Hz, None stops and drops the buffer
+    pub fn set_sample_rate(&mut self, sample_rate: Option<u32>) {
+        self.mixer = sample_rate.map(|rate| Mixer::new(rate, self.cgb));
+    }
+
+    pub fn sample_rate(&self) -> Option<u32> {
+        self.mixer.as_ref().map(|mixer| mixer.sample_rate())
+    }
+
+    // Also keeps each channel's output, see Mixer::set_channel_capture
+    pub fn set_channel_capture(&mut self, enabled: bool) {
+        if let Some(mixer) = &mut self.mixer {
+            mixer.set_channel_capture(enabled);
+        }
+    }
+
+    pub fn take_channel_samples(&mut self) -> [Vec<f32>; 4] {
+        match &mut self.mixer {
+            Some(mixer) => mixer.take_channel_samples(),
+            None => Default::default(),
+        }
+    }
+
+    // Interleaved stereo samples mixed since the last call
+    pub fn take_samples(&mut self) -> Vec<f32> {
+        match &mut self.mixer {
+            Some(mixer) => mixer.take_samples(),
+            None => Vec::new(),
+        }
+    }
+
+    // Digital output (0-15) of each channel
+    pub fn channel_outputs(&self) -> [u8; 4] {
+        [self.channel1.output(), self.channel2.output(), self.channel3.output(), self.channel4.output()]
+    }
+
+    fn register(&self, register: HardwareRegister) -> u8 {
+        self.registers[(register as u16 - APU_START) as usize]
+    }
+
+    fn set_raw(&mut self, address: u16, value: u8) {
+        self.registers[(address - APU_START) as usize] = value;
+    }
+
+    // Raw register value, without the bits that read back as 1
+    pub fn read(&self, address: u16) -> u8 {
+        if address == HardwareRegister::NR52 as u16 {
+            let mut nr52 = if self.powered { NR52_POWER } else { 0 };
+            let status = [
+                self.channel1.enabled(),
+                self.channel2.enabled(),
+                self.channel3.enabled(),
+                self.channel4.enabled(),
+            ];
+            for (i, enabled) in status.into_iter().enumerate() {
+                if enabled {
+                    nr52 |= 1 << i;
+                }
+            }
+            return nr52;
+        }
+        if address >= WAVE_RAM_START {
+            return self.channel3.read_ram((address - WAVE_RAM_START) as usize, self.cgb);
+        }
+        self.registers[(address - APU_START) as usize]
+    }
+
+    // CPU write
+    pub fn write(&mut self, address: u16, value: u8) {
+        if address == HardwareRegister::NR52 as u16 {
+            self.set_power(value & NR52_POWER != 0);
+            return;
+        }
+        if address >= WAVE_RAM_START {
+            self.channel3.write_ram((address - WAVE_RAM_START) as usize, value, self.cgb);
+            return;
+        }
+        if !self.powered {
+            // Powered off everything but NR52 and wave RAM ignores writes,
+            // except the length counters on DMG
+            if !self.cgb {
+                self.write_length_only(address, value);
+            }
+            return;
+        }
+        self.set_raw(address, value);
+        self.write_channel(address, value);
+    }
+
+    // Sets a register without triggering anything, for the state the boot ROM
+    // leaves behind. NR52 sets the power and channel status bits as given.
+    pub fn set_register(&mut self, address: u16, value: u8) {
+        if address == HardwareRegister::NR52 as u16 {
+            self.powered = value & NR52_POWER != 0;
+            self.channel1.set_enabled(value & 0b01 != 0);
+            self.channel2.set_enabled(value & 0b10 != 0);
+            self.channel3.set_enabled(value & 0b100 != 0);
+            self.channel4.set_enabled(value & 0b1000 != 0);
+            return;
+        }
+        if address >= WAVE_RAM_START {
+            self.channel3.set_ram((address - WAVE_RAM_START) as usize, value);
+            return;
+        }
+        self.set_raw(address, value);
+        let triggerless = if is_control_register(address) { value & 0x7F } else { value };
+        self.write_channel(address, triggerless);
+    }
+
+    fn write_channel(&mut self, address: u16, value: u8) {
+        // Extra length clocks happen while the next step doesn't clock length
+        let length_pending = self.frame_step % 2 == 1;
+        let envelope_pending = self.frame_step == 7;
+
+        match address {
+            0xFF10 => self.channel1.write_sweep(value),
+            0xFF11 => self.channel1.write_length(value),
+            0xFF12 => self.channel1.write_envelope(value),
+            0xFF13 => self.channel1.write_frequency_low(value),
+            0xFF14 => self.channel1.write_control(value, length_pending, envelope_pending),
+            0xFF16 => self.channel2.write_length(value),
+            0xFF17 => self.channel2.write_envelope(value),
+            0xFF18 => self.channel2.write_frequency_low(value),
+            0xFF19 => self.channel2.write_control(value, length_pending, envelope_pending),
+            0xFF1A => self.channel3.write_dac(value),
+            0xFF1B => self.channel3.write_length(value),
+            0xFF1C => self.channel3.write_output_level(value),
+            0xFF1D => self.channel3.write_frequency_low(value),
+            0xFF1E => self.channel3.write_control(value, length_pending),
+            0xFF20 => self.channel4.write_length(value),
+            0xFF21 => self.channel4.write_envelope(value),
+            0xFF22 => self.channel4.write_polynomial(value),
+            0xFF23 => self.channel4.write_control(value, length_pending, envelope_pending),
+            _ => {}
+        }
+    }
+
+    fn write_length_only(&mut self, address: u16, value: u8) {
+        match address {
+            0xFF11 => self.channel1.length.load(value & 0x3F),
+            0xFF16 => self.channel2.length.load(value & 0x3F),
+            0xFF1B => self.channel3.length.load(value),
+            0xFF20 => self.channel4.length.load(value & 0x3F),
+            _ => {}
+        }
+    }
+
+    fn set_power(&mut self, on: bool) {
+        if on == self.powered {
+            return;
+        }
+        self.powered = on;
+        if on {
+            // The first step after power on is step 0
+            self.frame_step = 0;
+            return;
+        }
+
+        // Power off clears every register up to NR51, wave RAM is kept
+        self.registers.fill(0);
+        // DMG keeps the length counters while powered off
+        self.channel1.power_off(!self.cgb);
+        self.channel2.power_off(!self.cgb);
+        self.channel3.power_off(!self.cgb);
+        self.channel4.power_off(!self.cgb);
+    }
+
+    // Advances the channels and clocks the frame sequencer on a falling edge of
+    // DIV bit 4
+    pub fn step(&mut self, cycles: u16, div: u8) {
+        let div_bit = div & DIV_FRAME_SEQUENCER_BIT != 0;
+        let falling_edge = self.div_bit && !div_bit;
+        self.div_bit = div_bit;
+
+        if self.mixer.is_some() {
+            // Step in M-cycles so the mix sees every change of the channel outputs
+            let mut remaining = cycles;
+            while remaining > 0 {
+                let chunk = remaining.min(MIX_CYCLES);
+                remaining -= chunk;
+                self.step_channels(chunk);
+                let channels = self.dac_outputs();
+                let (left, right, dacs_on) = self.stereo_output(channels);
+                if let Some(mixer) = &mut self.mixer {
+                    mixer.mix(chunk as u32, left, right, dacs_on, channels);
+                }
+            }
+        } else {
+            self.step_channels(cycles);
+        }
+
+        if self.powered && falling_edge {
+            self.clock_frame_sequencer();
+        }
+    }
+
+    fn step_channels(&mut self, cycles: u16) {
+        if !self.powered {
+            return;
+        }
+        self.channel1.step(cycles as u32);
+        self.channel2.step(cycles as u32);
+        self.channel3.step(cycles as u32);
+        self.channel4.step(cycles as u32);
+    }
+
+    // Analog output of the four channel DACs
+    fn dac_outputs(&self) -> [f32; 4] {
+        if !self.powered {
+            return [0.0; 4];
+        }
+        [
+            mixer::dac_output(self.channel1.output(), self.channel1.dac_enabled()),
+            mixer::dac_output(self.channel2.output(), self.channel2.dac_enabled()),
+            mixer::dac_output(self.channel3.output(), self.channel3.dac_enabled()),
+            mixer::dac_output(self.channel4.output(), self.channel4.dac_enabled()),
+        ]
+    }
+
+    // NR51 routes each channel to the left (bits 7-4) and right (bits 3-0)
+    // terminal, NR50 scales each side by (volume + 1) / 8. Returns whether any
+    // DAC is on, for the high-pass filter.
+    fn stereo_output(&self, channels: [f32; 4]) -> (f32, f32, bool) {
+        if !self.powered {
+            return (0.0, 0.0, false);
+        }
+        let nr50 = self.register(HardwareRegister::NR50);
+        let nr51 = self.register(HardwareRegister::NR51);
+
+        let (mut left, mut right) = (0.0, 0.0);
+        for (i, analog) in channels.into_iter().enumerate() {
+            if nr51 & (0x10 << i) != 0 {
+                left += analog;
+            }
+            if nr51 & (0x01 << i) != 0 {
+                right += analog;
+            }
+        }
+        let left_volume = ((nr50 >> 4) & 0b111) as f32 + 1.0;
+        let right_volume = (nr50 & 0b111) as f32 + 1.0;
+        let dacs_on = self.channel1.dac_enabled()
+            || self.channel2.dac_enabled()
+            || self.channel3.dac_enabled()
+            || self.channel4.dac_enabled();
+        (left / 4.0 * left_volume / 8.0, right / 4.0 * right_volume / 8.0, dacs_on)
+    }
+
+    fn clock_frame_sequencer(&mut self) {
+        let step = self.frame_step;
+        self.frame_step = (self.frame_step + 1) % 8;
+
+        if step.is_multiple_of(2) {
+            for channel in [&mut self.channel1, &mut self.channel2] {
+                if channel.length.clock() {
+                    channel.disable();
+                }
+            }
+            if self.channel3.length.clock() {
+                self.channel3.disable();
+            }
+            if self.channel4.length.clock() {
+                self.channel4.disable();
+            }
+        }
+        if (step == 2 || step == 6)
+            && let Some(frequency) = self.channel1.clock_sweep()
+        {
+            let nr14 = self.register(HardwareRegister::NR14);
+            self.set_raw(HardwareRegister::NR13 as u16, frequency as u8);
+            self.set_raw(HardwareRegister::NR14 as u16, (nr14 & !0b111) | (frequency >> 8) as u8);
+        }
+        if step == 7 {
+            self.channel1.envelope.clock();
+            self.channel2.envelope.clock();
+            self.channel4.envelope.clock();
+        }
+    }
+}
+
+// NRx4: trigger, length enable and the upper frequency bits
+fn is_control_register(address: u16) -> bool {
+    matches!(address, 0xFF14 | 0xFF19 | 0xFF1E | 0xFF23)
+}
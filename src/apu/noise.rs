@@ -0,0 +1,303 @@
+use super::envelope::{self, Envelope};
+use super::length::LengthCounter;
+
+// NR43 bits 2-0 pick the base divisor, bits 7-4 shift it left
+const DIVISORS: [u32; 8] = [8, 16, 32, 48, 64, 80, 96, 112];
+// Clock shifts of 14 and 15 stop the LFSR
+const MAX_CLOCK_SHIFT: u8 = 13;
+
+const LFSR_RESET: u16 = 0x7FFF;
+
+// Channel 4 outputs the inverted low bit of a 15 bit LFSR. Each clock XORs
+// bits 0 and 1 and shifts the result in at bit 14, and also at bit 6 in 7 bit
+// mode (NR43 bit 3) for a shorter, more tonal sequence.
+pub struct NoiseChannel {
+    enabled: bool,
+    dac_enabled: bool,
+    clock_shift: u8,
+    short_mode: bool,
+    divisor_code: u8,
+    timer: u32, // cycles until the next LFSR clock
+    lfsr: u16,
+    pub length: LengthCounter,
+    pub envelope: Envelope,
+}
+
+impl NoiseChannel {
+    pub fn new() -> Self {
+        NoiseChannel {
+            enabled: false,
+            dac_enabled: false,
+            clock_shift: 0,
+            short_mode: false,
+            divisor_code: 0,
+            timer: 0,
+            lfsr: LFSR_RESET,
+            length: LengthCounter::new(64),
+            envelope: Envelope::new(),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn dac_enabled(&self) -> bool {
+        self.dac_enabled
+    }
+
+    pub fn disable(&mut self) {
+        self.enabled = false;
+    }
+
+    // Sets the channel status directly, for registers preset without a boot ROM
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled && self.dac_enabled;
+    }
+
+    // Clears everything the registers set
+    pub fn power_off(&mut self, keep_length: bool) {
+        let length = std::mem::replace(&mut self.length, LengthCounter::new(64));
+        *self = NoiseChannel::new();
+        if keep_length {
+            self.length = length;
+            self.length.set_enabled(false, false);
+        }
+    }
+
+    pub fn write_length(&mut self, value: u8) {
+        self.length.load(value & 0x3F);
+    }
+
+    pub fn write_envelope(&mut self, value: u8) {
+        self.envelope.write(value);
+        self.dac_enabled = envelope::dac_enabled(value);
+        if !self.dac_enabled {
+            self.enabled = false;
+        }
+    }
+
+    pub fn write_polynomial(&mut self, value: u8) {
+        self.clock_shift = value >> 4;
+        self.short_mode = value & 0b0000_1000 != 0;
+        self.divisor_code = value & 0b111;
+    }
+
+    // NR44, see PulseChannel::write_control
+    pub fn write_control(&mut self, value: u8, length_pending: bool, envelope_pending: bool) {
+        let trigger = value & 0b1000_0000 != 0;
+        if self.length.set_enabled(value & 0b0100_0000 != 0, length_pending) && !trigger {
+            self.enabled = false;
+        }
+        if trigger {
+            self.enabled = self.dac_enabled;
+            self.length.trigger(length_pending);
+            self.timer = self.period();
+            self.envelope.trigger(envelope_pending);
+            self.lfsr = LFSR_RESET;
+        }
+    }
+
+    fn period(&self) -> u32 {
+        DIVISORS[self.divisor_code as usize] << self.clock_shift
+    }
+
+    fn clock_lfsr(&mut self) {
+        let bit = (self.lfsr ^ (self.lfsr >> 1)) & 1;
+        self.lfsr = (self.lfsr >> 1) | (bit << 14);
+        if self.short_mode {
+            self.lfsr = (self.lfsr & !(1 << 6)) | (bit << 6);
+        }
+    }
+
+    pub fn step(&mut self, cycles: u32) {
+        let mut cycles = cycles;
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = self.period();
+            if self.clock_shift <= MAX_CLOCK_SHIFT {
+                self.clock_lfsr();
+            }
+        }
+        self.timer -= cycles;
+    }
+
+    // Digital output, 0-15
+    pub fn output(&self) -> u8 {
+        if !self.enabled || self.lfsr & 1 != 0 {
+            return 0;
+        }
+        self.envelope.volume()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // First outputs after a trigger, 1 while the channel is high
+    const LONG_OUTPUT: &str = "0000000000000011111111111111011111111111110011111111111101011111";
+    const SHORT_OUTPUT: &str = "0000001111110111110011110101110000110111010011000101011000001011";
+
+    // The LFSR as Pan Docs and SameBoy describe it: cleared on trigger, bit 0
+    // XNOR bit 1 shifted in at the top (and into bit 6 in 7 bit mode), the channel
+    // is high while bit 0 is set. The channel keeps the complement of this.
+    struct DocumentedLfsr(u16);
+
+    impl DocumentedLfsr {
+        fn clock(&mut self, short_mode: bool) -> bool {
+            let high = !(self.0 ^ (self.0 >> 1)) & 1 != 0;
+            let mask = if short_mode { 0x4040 } else { 0x4000 };
+            self.0 >>= 1;
+            if high {
+                self.0 |= mask;
+            } else {
+                self.0 &= !mask;
+            }
+            self.0 & 1 != 0
+        }
+    }
+
+    // Full volume, one LFSR clock every 8 cycles
+    fn triggered(nr43: u8) -> NoiseChannel {
+        let mut channel = NoiseChannel::new();
+        channel.write_envelope(0xF0);
+        channel.write_polynomial(nr43);
+        channel.write_control(0x80, false, false);
+        channel
+    }
+
+    fn clock(channel: &mut NoiseChannel) -> u16 {
+        channel.step(8);
+        channel.lfsr
+    }
+
+    fn high(channel: &mut NoiseChannel) -> bool {
+        channel.step(8);
+        match channel.output() {
+            15 => true,
+            0 => false,
+            volume => panic!("unexpected volume {}", volume),
+        }
+    }
+
+    #[test]
+    fn matches_the_documented_lfsr() {
+        for (nr43, short_mode) in [(0x00, false), (0x08, true)] {
+            let mut channel = triggered(nr43);
+            let mut reference = DocumentedLfsr(0);
+            for i in 0..2 * 32767 {
+                assert_eq!(high(&mut channel), reference.clock(short_mode), "NR43 {:#04X} clock {}", nr43, i);
+            }
+        }
+    }
+
+    #[test]
+    fn documented_first_outputs() {
+        // 14 clocks until the first bit shifted in at the top reaches bit 0, 6 in
+        // 7 bit mode
+        for (short_mode, expected) in [(false, LONG_OUTPUT), (true, SHORT_OUTPUT)] {
+            let mut reference = DocumentedLfsr(0);
+            let output: String = expected.chars().map(|_| if reference.clock(short_mode) { '1' } else { '0' }).collect();
+            assert_eq!(output, expected);
+        }
+        assert!(LONG_OUTPUT.starts_with("000000000000001"));
+        assert!(SHORT_OUTPUT.starts_with("0000001"));
+    }
+
+    #[test]
+    fn output_is_the_inverted_low_bit() {
+        for (nr43, expected) in [(0x00, LONG_OUTPUT), (0x08, SHORT_OUTPUT)] {
+            let mut channel = triggered(nr43);
+            let output: String = expected
+                .chars()
+                .map(|_| {
+                    channel.step(8);
+                    match channel.output() {
+                        15 => '1',
+                        0 => '0',
+                        volume => panic!("unexpected volume {}", volume),
+                    }
+                })
+                .collect();
+            assert_eq!(output, expected, "NR43 {:#04X}", nr43);
+        }
+    }
+
+    // The output sequence repeats after exactly 32767 clocks, 7 * 31 * 151, and
+    // not after any divisor of it; 127 is prime
+    #[test]
+    fn periods() {
+        for (nr43, period, divisors) in [(0x00, 32767, &[4681, 1057, 217][..]), (0x08, 127, &[1][..])] {
+            let mut channel = triggered(nr43);
+            let output: Vec<bool> = (0..2 * period).map(|_| high(&mut channel)).collect();
+            assert!((0..period).all(|i| output[i] == output[i + period]), "NR43 {:#04X}", nr43);
+            for &divisor in divisors {
+                assert!((0..period).any(|i| output[i] != output[i + divisor]), "NR43 {:#04X} repeats after {}", nr43, divisor);
+            }
+        }
+    }
+
+    #[test]
+    fn switching_width_mid_sequence() {
+        // Pan Docs and SameBoy keep clocking from the current state, 7 bit mode
+        // only adds the write to bit 6
+        let mut channel = triggered(0x00);
+        let mut reference = DocumentedLfsr(0);
+        let mut short_mode = false;
+        let switches = [5, 13, 14, 40, 41, 300, 1000, 1001, 5000];
+        for clock in 0..6000 {
+            if switches.contains(&clock) {
+                short_mode = !short_mode;
+                channel.write_polynomial(if short_mode { 0x08 } else { 0x00 });
+            }
+            assert_eq!(high(&mut channel), reference.clock(short_mode), "clock {}", clock);
+        }
+    }
+
+    #[test]
+    fn switching_to_seven_bits_can_lock_up() {
+        let mut channel = triggered(0x00);
+        for _ in 0..20 {
+            clock(&mut channel);
+        }
+        assert_eq!(channel.lfsr, 0x0200);
+        channel.write_polynomial(0x08);
+        assert_eq!(clock(&mut channel), 0x0100);
+        assert_eq!(clock(&mut channel), 0x0080);
+        for _ in 0..200 {
+            assert_eq!(clock(&mut channel), 0x0000);
+        }
+        assert_eq!(channel.output(), 15);
+    }
+
+    #[test]
+    fn divisor_and_shift_set_the_clock_rate() {
+        // Divisor code 1 (16) shifted left by 2
+        let mut channel = triggered(0x21);
+        channel.step(63);
+        assert_eq!(channel.lfsr, LFSR_RESET);
+        channel.step(1);
+        assert_eq!(channel.lfsr, 0x3FFF);
+    }
+
+    #[test]
+    fn shifts_14_and_15_stop_the_lfsr() {
+        for nr43 in [0xE0, 0xF0] {
+            let mut channel = triggered(nr43);
+            channel.step(u16::MAX as u32 * 4);
+            assert_eq!(channel.lfsr, LFSR_RESET);
+        }
+    }
+
+    #[test]
+    fn trigger_resets_the_lfsr() {
+        let mut channel = triggered(0x00);
+        for _ in 0..10 {
+            clock(&mut channel);
+        }
+        channel.write_control(0x80, false, false);
+        assert_eq!(channel.lfsr, LFSR_RESET);
+        assert_eq!(clock(&mut channel), 0x3FFF);
+    }
+}
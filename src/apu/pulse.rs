@@ -0,0 +1,212 @@
+use super::envelope::{self, Envelope};
+use super::length::LengthCounter;
+
+// Waveforms for NRx1 bits 7-6: 12.5%, 25%, 50% and 75% duty, played from bit 7 down
+const DUTY_WAVEFORMS: [u8; 4] = [0b0000_0001, 0b1000_0001, 0b1000_0111, 0b0111_1110];
+
+const MAX_FREQUENCY: u16 = 2047;
+
+// Frequency sweep of channel 1, driven by NR10: period (bits 6-4),
+// negate (bit 3) and shift (bits 2-0). Clocked at 128 Hz.
+struct Sweep {
+    period: u8,
+    negate: bool,
+    shift: u8,
+    timer: u8,
+    enabled: bool,
+    shadow: u16,
+    negated: bool, // a calculation in negate mode ran since the last trigger
+}
+
+impl Sweep {
+    fn new() -> Self {
+        Sweep { period: 0, negate: false, shift: 0, timer: 0, enabled: false, shadow: 0, negated: false }
+    }
+
+    fn reload_timer(&mut self) {
+        self.timer = if self.period == 0 { 8 } else { self.period };
+    }
+
+    fn calculate(&mut self) -> u16 {
+        let delta = self.shadow >> self.shift;
+        if self.negate {
+            self.negated = true;
+            self.shadow - delta
+        } else {
+            self.shadow + delta
+        }
+    }
+}
+
+// Channels 1 and 2. Channel 1 is the only one with a sweep unit.
+pub struct PulseChannel {
+    enabled: bool,
+    dac_enabled: bool,
+    duty: u8,
+    duty_position: u8,
+    frequency: u16,
+    timer: u32, // cycles until the next duty step
+    pub length: LengthCounter,
+    pub envelope: Envelope,
+    sweep: Option<Sweep>,
+}
+
+impl PulseChannel {
+    pub fn new(with_sweep: bool) -> Self {
+        PulseChannel {
+            enabled: false,
+            dac_enabled: false,
+            duty: 0,
+            duty_position: 0,
+            frequency: 0,
+            timer: 0,
+            length: LengthCounter::new(64),
+            envelope: Envelope::new(),
+            sweep: with_sweep.then(Sweep::new),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn dac_enabled(&self) -> bool {
+        self.dac_enabled
+    }
+
+    pub fn disable(&mut self) {
+        self.enabled = false;
+    }
+
+    // Sets the channel status directly, for registers preset without a boot ROM
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled && self.dac_enabled;
+    }
+
+    // Clears everything the registers set, and restarts the waveform from the first step
+    pub fn power_off(&mut self, keep_length: bool) {
+        let length = std::mem::replace(&mut self.length, LengthCounter::new(64));
+        *self = PulseChannel::new(self.sweep.is_some());
+        if keep_length {
+            self.length = length;
+            self.length.set_enabled(false, false);
+        }
+    }
+
+    pub fn write_sweep(&mut self, value: u8) {
+        let Some(sweep) = &mut self.sweep else {
+            return;
+        };
+        sweep.period = (value >> 4) & 0b111;
+        let negate = value & 0b0000_1000 != 0;
+        // Leaving negate mode after it has been used switches the channel off
+        if sweep.negate && !negate && sweep.negated {
+            self.enabled = false;
+        }
+        sweep.negate = negate;
+        sweep.shift = value & 0b111;
+    }
+
+    pub fn write_length(&mut self, value: u8) {
+        self.duty = value >> 6;
+        self.length.load(value & 0x3F);
+    }
+
+    pub fn write_envelope(&mut self, value: u8) {
+        self.envelope.write(value);
+        self.dac_enabled = envelope::dac_enabled(value);
+        if !self.dac_enabled {
+            self.enabled = false;
+        }
+    }
+
+    pub fn write_frequency_low(&mut self, value: u8) {
+        self.frequency = (self.frequency & 0x700) | value as u16;
+    }
+
+    // NRx4. `length_pending` is set while the next frame sequencer step doesn't
+    // clock length, `envelope_pending` while it clocks the envelope.
+    pub fn write_control(&mut self, value: u8, length_pending: bool, envelope_pending: bool) {
+        self.frequency = (self.frequency & 0xFF) | ((value as u16 & 0b111) << 8);
+
+        let trigger = value & 0b1000_0000 != 0;
+        if self.length.set_enabled(value & 0b0100_0000 != 0, length_pending) && !trigger {
+            self.enabled = false;
+        }
+        if trigger {
+            self.trigger(length_pending, envelope_pending);
+        }
+    }
+
+    fn trigger(&mut self, length_pending: bool, envelope_pending: bool) {
+        self.enabled = self.dac_enabled;
+        self.length.trigger(length_pending);
+        self.timer = self.period();
+        self.envelope.trigger(envelope_pending);
+
+        if let Some(sweep) = &mut self.sweep {
+            sweep.shadow = self.frequency;
+            sweep.reload_timer();
+            sweep.enabled = sweep.period != 0 || sweep.shift != 0;
+            sweep.negated = false;
+            // With a shift the overflow check runs straight away
+            if sweep.shift != 0 && sweep.calculate() > MAX_FREQUENCY {
+                self.enabled = false;
+            }
+        }
+    }
+
+    // Four cycles per duty step
+    fn period(&self) -> u32 {
+        (2048 - self.frequency as u32) * 4
+    }
+
+    // The new frequency is written back to NR13/NR14, returns it so the
+    // register file can be updated
+    pub fn clock_sweep(&mut self) -> Option<u16> {
+        let sweep = self.sweep.as_mut()?;
+        sweep.timer = sweep.timer.saturating_sub(1);
+        if sweep.timer > 0 {
+            return None;
+        }
+        sweep.reload_timer();
+        if !sweep.enabled || sweep.period == 0 {
+            return None;
+        }
+
+        let frequency = sweep.calculate();
+        if frequency > MAX_FREQUENCY {
+            self.enabled = false;
+            return None;
+        }
+        if sweep.shift == 0 {
+            return None;
+        }
+        sweep.shadow = frequency;
+        self.frequency = frequency;
+        // The new frequency is checked again, but not used
+        if sweep.calculate() > MAX_FREQUENCY {
+            self.enabled = false;
+        }
+        Some(frequency)
+    }
+
+    pub fn step(&mut self, cycles: u32) {
+        let mut cycles = cycles;
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = self.period();
+            self.duty_position = (self.duty_position + 1) % 8;
+        }
+        self.timer -= cycles;
+    }
+
+    // Digital output, 0-15
+    pub fn output(&self) -> u8 {
+        if !self.enabled {
+            return 0;
+        }
+        let high = DUTY_WAVEFORMS[self.duty as usize] & (0x80 >> self.duty_position) != 0;
+        if high { self.envelope.volume() } else { 0 }
+    }
+}
@@ -0,0 +1,168 @@
+use super::length::LengthCounter;
+
+const WAVE_RAM_SIZE: usize = 16;
+
+// Extra delay before the first sample after a trigger
+const TRIGGER_DELAY: u32 = 6;
+// Cycles after the channel fetched a byte during which a DMG can still reach it
+const DMG_ACCESS_WINDOW: u32 = 4;
+
+// Channel 3 plays 32 4-bit samples from wave RAM, high nibble first, shifted
+// right by the output level in NR32 (mute, 100%, 50%, 25%)
+pub struct WaveChannel {
+    enabled: bool,
+    dac_enabled: bool,
+    output_level: u8,
+    frequency: u16,
+    timer: u32, // cycles until the next sample
+    position: u8,
+    sample: u8, // last byte read from wave RAM
+    since_read: u32, // cycles since wave RAM was last read
+    ram: [u8; WAVE_RAM_SIZE],
+    pub length: LengthCounter,
+}
+
+impl WaveChannel {
+    pub fn new() -> Self {
+        WaveChannel {
+            enabled: false,
+            dac_enabled: false,
+            output_level: 0,
+            frequency: 0,
+            timer: 0,
+            position: 0,
+            sample: 0,
+            since_read: u32::MAX,
+            ram: [0; WAVE_RAM_SIZE],
+            length: LengthCounter::new(256),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn dac_enabled(&self) -> bool {
+        self.dac_enabled
+    }
+
+    pub fn disable(&mut self) {
+        self.enabled = false;
+    }
+
+    // Sets the channel status directly, for registers preset without a boot ROM
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled && self.dac_enabled;
+    }
+
+    // Clears everything the registers set. Wave RAM survives.
+    pub fn power_off(&mut self, keep_length: bool) {
+        let length = std::mem::replace(&mut self.length, LengthCounter::new(256));
+        let ram = self.ram;
+        *self = WaveChannel::new();
+        self.ram = ram;
+        if keep_length {
+            self.length = length;
+            self.length.set_enabled(false, false);
+        }
+    }
+
+    pub fn write_dac(&mut self, value: u8) {
+        self.dac_enabled = value & 0b1000_0000 != 0;
+        if !self.dac_enabled {
+            self.enabled = false;
+        }
+    }
+
+    pub fn write_length(&mut self, value: u8) {
+        self.length.load(value);
+    }
+
+    pub fn write_output_level(&mut self, value: u8) {
+        self.output_level = (value >> 5) & 0b11;
+    }
+
+    pub fn write_frequency_low(&mut self, value: u8) {
+        self.frequency = (self.frequency & 0x700) | value as u16;
+    }
+
+    // NR34, see PulseChannel::write_control
+    pub fn write_control(&mut self, value: u8, length_pending: bool) {
+        self.frequency = (self.frequency & 0xFF) | ((value as u16 & 0b111) << 8);
+
+        let trigger = value & 0b1000_0000 != 0;
+        if self.length.set_enabled(value & 0b0100_0000 != 0, length_pending) && !trigger {
+            self.enabled = false;
+        }
+        if trigger {
+            self.enabled = self.dac_enabled;
+            self.length.trigger(length_pending);
+            self.timer = self.period() + TRIGGER_DELAY;
+            // Playback restarts at the first sample, the buffered byte isn't refreshed
+            self.position = 0;
+        }
+    }
+
+    // Two cycles per sample
+    fn period(&self) -> u32 {
+        (2048 - self.frequency as u32) * 2
+    }
+
+    // While the channel plays, the CPU only sees the byte it is reading. On DMG
+    // that's only possible right as the channel fetches it, otherwise the access misses.
+    fn accessible_index(&self, offset: usize, cgb: bool) -> Option<usize> {
+        if !self.enabled {
+            return Some(offset);
+        }
+        if cgb || self.since_read < DMG_ACCESS_WINDOW {
+            return Some(self.position as usize / 2);
+        }
+        None
+    }
+
+    pub fn read_ram(&self, offset: usize, cgb: bool) -> u8 {
+        match self.accessible_index(offset, cgb) {
+            Some(index) => self.ram[index],
+            None => 0xFF,
+        }
+    }
+
+    pub fn write_ram(&mut self, offset: usize, value: u8, cgb: bool) {
+        if let Some(index) = self.accessible_index(offset, cgb) {
+            self.ram[index] = value;
+        }
+    }
+
+    // Direct access, for registers preset without a boot ROM
+    pub fn set_ram(&mut self, offset: usize, value: u8) {
+        self.ram[offset] = value;
+    }
+
+    pub fn step(&mut self, cycles: u32) {
+        self.since_read = self.since_read.saturating_add(cycles);
+        if !self.enabled {
+            return;
+        }
+        let mut cycles = cycles;
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = self.period();
+            self.position = (self.position + 1) % 32;
+            self.sample = self.ram[self.position as usize / 2];
+            self.since_read = cycles;
+        }
+        self.timer -= cycles;
+    }
+
+    // Digital output, 0-15
+    pub fn output(&self) -> u8 {
+        if !self.enabled {
+            return 0;
+        }
+        let nibble = if self.position.is_multiple_of(2) { self.sample >> 4 } else { self.sample & 0x0F };
+        match self.output_level {
+            0 => 0,
+            level => nibble >> (level - 1),
+        }
+    }
+}
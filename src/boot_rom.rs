@@ -0,0 +1,40 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+pub const DMG_BOOT_ROM_SIZE: usize = 0x0100;
+pub const CGB_BOOT_ROM_SIZE: usize = 0x0900;
+
+// Overlays the cartridge at 0000-00FF (and 0200-08FF on CGB) until a
+// write to FF50 unmaps it. 0100-01FF always shows the cartridge header.
+pub struct BootRom {
+    data: Vec<u8>,
+}
+
+impl BootRom {
+    pub fn from_bytes(data: Vec<u8>) -> io::Result<Self> {
+        if data.len() != DMG_BOOT_ROM_SIZE && data.len() != CGB_BOOT_ROM_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("boot ROM must be {} or {} bytes, got {}", DMG_BOOT_ROM_SIZE, CGB_BOOT_ROM_SIZE, data.len()),
+            ));
+        }
+        Ok(BootRom { data })
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        BootRom::from_bytes(fs::read(path)?)
+    }
+
+    pub fn is_cgb(&self) -> bool {
+        self.data.len() == CGB_BOOT_ROM_SIZE
+    }
+
+    pub fn read(&self, address: u16) -> Option<u8> {
+        match address {
+            0x0000..=0x00FF => Some(self.data[address as usize]),
+            0x0200..=0x08FF if self.is_cgb() => Some(self.data[address as usize]),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,507 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::mbc::{MemoryBankController, RomOnly, Mbc1, Mbc2, Mbc3, Mbc5, RumbleEvent};
+
+// Cartridge header layout (0x0100 - 0x014F)
+// 0100-0103	Entry point
+// 0104-0133	Nintendo logo
+// 0134-0143	Title (0134-013E when a manufacturer code is present)
+// 013F-0142	Manufacturer code
+// 0143	CGB flag
+// 0144-0145	New licensee code
+// 0146	SGB flag
+// 0147	Cartridge type
+// 0148	ROM size
+// 0149	RAM size
+// 014A	Destination code
+// 014B	Old licensee code
+// 014C	Mask ROM version number
+// 014D	Header checksum
+// 014E-014F	Global checksum
+
+pub const ROM_BANK_SIZE: usize = 0x4000;
+pub const RAM_BANK_SIZE: usize = 0x2000;
+
+const HEADER_END: usize = 0x0150;
+
+#[derive(Debug)]
+pub enum CartridgeError {
+    Io(io::Error),
+    Truncated { expected: usize, actual: usize },
+    InvalidRomSize(u8),
+    InvalidRamSize(u8),
+    UnsupportedCartridgeType(u8),
+    SizeMismatch { expected: usize, actual: usize },
+    HeaderChecksum { expected: u8, actual: u8 },
+}
+
+impl fmt::Display for CartridgeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CartridgeError::Io(err) => write!(f, "failed to read ROM: {}", err),
+            CartridgeError::Truncated { expected, actual } => {
+                write!(f, "ROM is truncated: expected at least {:#X} bytes, got {:#X}", expected, actual)
+            }
+            CartridgeError::InvalidRomSize(code) => write!(f, "invalid ROM size code {:#04X}", code),
+            CartridgeError::InvalidRamSize(code) => write!(f, "invalid RAM size code {:#04X}", code),
+            CartridgeError::UnsupportedCartridgeType(code) => write!(f, "unsupported cartridge type {:#04X}", code),
+            CartridgeError::SizeMismatch { expected, actual } => {
+                write!(f, "ROM size mismatch: header declares {:#X} bytes, file has {:#X}", expected, actual)
+            }
+            CartridgeError::HeaderChecksum { expected, actual } => {
+                write!(f, "header checksum mismatch: header says {:#04X}, computed {:#04X}", expected, actual)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CartridgeError {}
+
+impl From<io::Error> for CartridgeError {
+    fn from(err: io::Error) -> Self {
+        CartridgeError::Io(err)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CgbFlag {
+    None,
+    Compatible, // 0x80 - works on DMG too
+    Only,       // 0xC0 - CGB only
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mapper {
+    None,
+    Mbc1,
+    Mbc2,
+    Mbc3,
+    Mbc5,
+    Mbc6,
+    Mbc7,
+    Mmm01,
+    PocketCamera,
+    Tama5,
+    HuC1,
+    HuC3,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CartridgeType {
+    pub code: u8,
+    pub mapper: Mapper,
+    pub ram: bool,
+    pub battery: bool,
+    pub rtc: bool,
+    pub rumble: bool,
+}
+
+impl CartridgeType {
+    pub fn from_code(code: u8) -> Option<Self> {
+        // (mapper, ram, battery, rtc, rumble)
+        let (mapper, ram, battery, rtc, rumble) = match code {
+            0x00 => (Mapper::None, false, false, false, false),
+            0x01 => (Mapper::Mbc1, false, false, false, false),
+            0x02 => (Mapper::Mbc1, true, false, false, false),
+            0x03 => (Mapper::Mbc1, true, true, false, false),
+            0x05 => (Mapper::Mbc2, false, false, false, false),
+            0x06 => (Mapper::Mbc2, false, true, false, false),
+            0x08 => (Mapper::None, true, false, false, false),
+            0x09 => (Mapper::None, true, true, false, false),
+            0x0B => (Mapper::Mmm01, false, false, false, false),
+            0x0C => (Mapper::Mmm01, true, false, false, false),
+            0x0D => (Mapper::Mmm01, true, true, false, false),
+            0x0F => (Mapper::Mbc3, false, true, true, false),
+            0x10 => (Mapper::Mbc3, true, true, true, false),
+            0x11 => (Mapper::Mbc3, false, false, false, false),
+            0x12 => (Mapper::Mbc3, true, false, false, false),
+            0x13 => (Mapper::Mbc3, true, true, false, false),
+            0x19 => (Mapper::Mbc5, false, false, false, false),
+            0x1A => (Mapper::Mbc5, true, false, false, false),
+            0x1B => (Mapper::Mbc5, true, true, false, false),
+            0x1C => (Mapper::Mbc5, false, false, false, true),
+            0x1D => (Mapper::Mbc5, true, false, false, true),
+            0x1E => (Mapper::Mbc5, true, true, false, true),
+            0x20 => (Mapper::Mbc6, false, false, false, false),
+            0x22 => (Mapper::Mbc7, true, true, false, true),
+            0xFC => (Mapper::PocketCamera, true, true, false, false),
+            0xFD => (Mapper::Tama5, true, true, true, false),
+            0xFE => (Mapper::HuC3, true, true, true, false),
+            0xFF => (Mapper::HuC1, true, true, false, false),
+            _ => return None,
+        };
+        Some(CartridgeType { code, mapper, ram, battery, rtc, rumble })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Licensee {
+    Old(u8),
+    New(String),
+}
+
+impl fmt::Display for Licensee {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Licensee::Old(code) => write!(f, "{:02X}", code),
+            Licensee::New(code) => write!(f, "{}", code),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CartridgeHeader {
+    pub title: String,
+    pub cgb_flag: CgbFlag,
+    pub sgb: bool,
+    pub cartridge_type: CartridgeType,
+    pub rom_size: usize,
+    pub ram_size: usize,
+    pub licensee: Licensee,
+    pub japanese: bool,
+    pub version: u8,
+    pub header_checksum: u8,
+    pub global_checksum: u16,
+}
+
+impl CartridgeHeader {
+    pub fn parse(rom: &[u8]) -> Result<Self, CartridgeError> {
+        if rom.len() < HEADER_END {
+            return Err(CartridgeError::Truncated { expected: HEADER_END, actual: rom.len() });
+        }
+
+        let cgb_flag = match rom[0x0143] {
+            0x80 => CgbFlag::Compatible,
+            0xC0 => CgbFlag::Only,
+            _ => CgbFlag::None,
+        };
+
+        // Newer carts shrink the title to make room for the manufacturer code and CGB flag
+        let title_end = if cgb_flag == CgbFlag::None { 0x0144 } else { 0x0143 };
+        let title = rom[0x0134..title_end]
+            .iter()
+            .take_while(|&&byte| byte != 0)
+            .map(|&byte| if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '?' })
+            .collect::<String>()
+            .trim_end()
+            .to_string();
+
+        let cartridge_type = CartridgeType::from_code(rom[0x0147])
+            .ok_or(CartridgeError::UnsupportedCartridgeType(rom[0x0147]))?;
+
+        let rom_size = match rom[0x0148] {
+            code @ 0x00..=0x08 => (32 * 1024) << code,
+            code => return Err(CartridgeError::InvalidRomSize(code)),
+        };
+
+        let ram_size = match rom[0x0149] {
+            0x00 => 0,
+            0x01 => 2 * 1024,
+            0x02 => 8 * 1024,
+            0x03 => 32 * 1024,
+            0x04 => 128 * 1024,
+            0x05 => 64 * 1024,
+            code => return Err(CartridgeError::InvalidRamSize(code)),
+        };
+
+        let licensee = if rom[0x014B] == 0x33 {
+            Licensee::New(String::from_utf8_lossy(&rom[0x0144..0x0146]).into_owned())
+        } else {
+            Licensee::Old(rom[0x014B])
+        };
+
+        let header_checksum = rom[0x014D];
+        let computed = header_checksum_of(rom);
+        if computed != header_checksum {
+            return Err(CartridgeError::HeaderChecksum { expected: header_checksum, actual: computed });
+        }
+
+        Ok(CartridgeHeader {
+            title,
+            cgb_flag,
+            sgb: rom[0x0146] == 0x03,
+            cartridge_type,
+            rom_size,
+            ram_size,
+            licensee,
+            japanese: rom[0x014A] == 0x00,
+            version: rom[0x014C],
+            header_checksum,
+            global_checksum: ((rom[0x014E] as u16) << 8) | rom[0x014F] as u16,
+        })
+    }
+
+    pub fn rom_banks(&self) -> usize {
+        self.rom_size / ROM_BANK_SIZE
+    }
+
+    pub fn ram_banks(&self) -> usize {
+        self.ram_size.div_ceil(RAM_BANK_SIZE)
+    }
+}
+
+// Checksum over 0x0134-0x014C, verified by the boot ROM
+pub fn header_checksum_of(rom: &[u8]) -> u8 {
+    rom[0x0134..=0x014C]
+        .iter()
+        .fold(0u8, |sum, &byte| sum.wrapping_sub(byte).wrapping_sub(1))
+}
+
+// Sum of every byte except the checksum itself, not verified by hardware
+pub fn global_checksum_of(rom: &[u8]) -> u16 {
+    rom.iter()
+        .enumerate()
+        .filter(|&(i, _)| i != 0x014E && i != 0x014F)
+        .fold(0u16, |sum, (_, &byte)| sum.wrapping_add(byte as u16))
+}
+
+pub struct Cartridge {
+    pub header: CartridgeHeader,
+    mbc: Box<dyn MemoryBankController>,
+    computed_global_checksum: u16,
+}
+
+impl Cartridge {
+    pub fn from_bytes(mut rom: Vec<u8>) -> Result<Self, CartridgeError> {
+        let header = CartridgeHeader::parse(&rom)?;
+
+        if rom.len() < header.rom_size {
+            return Err(CartridgeError::Truncated { expected: header.rom_size, actual: rom.len() });
+        }
+        // Overdumped or padded files carry junk past the declared size, which the
+        // cartridge can't address anyway
+        rom.truncate(header.rom_size);
+        // Without a mapper only the two fixed banks are addressable
+        if header.cartridge_type.mapper == Mapper::None && header.rom_size > 2 * ROM_BANK_SIZE {
+            return Err(CartridgeError::SizeMismatch { expected: 2 * ROM_BANK_SIZE, actual: header.rom_size });
+        }
+
+        let computed_global_checksum = global_checksum_of(&rom);
+        let ram_size = header.ram_size;
+        let mbc: Box<dyn MemoryBankController> = match header.cartridge_type.mapper {
+            Mapper::None => Box::new(RomOnly::new(rom, ram_size)),
+            Mapper::Mbc1 => Box::new(Mbc1::new(rom, ram_size)),
+            Mapper::Mbc2 => Box::new(Mbc2::new(rom)),
+            Mapper::Mbc3 => Box::new(Mbc3::new(rom, ram_size, header.cartridge_type.rtc)),
+            Mapper::Mbc5 => Box::new(Mbc5::new(rom, ram_size, header.cartridge_type.rumble)),
+            _ => return Err(CartridgeError::UnsupportedCartridgeType(header.cartridge_type.code)),
+        };
+
+        Ok(Cartridge { header, mbc, computed_global_checksum })
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, CartridgeError> {
+        let rom = fs::read(path)?;
+        Cartridge::from_bytes(rom)
+    }
+
+    pub fn global_checksum_valid(&self) -> bool {
+        self.computed_global_checksum == self.header.global_checksum
+    }
+
+    pub fn read_rom(&self, address: u16) -> u8 {
+        self.mbc.read_rom(address)
+    }
+
+    pub fn write_rom(&mut self, address: u16, value: u8) {
+        self.mbc.write_rom(address, value);
+    }
+
+    pub fn read_ram(&self, address: u16) -> u8 {
+        self.mbc.read_ram(address)
+    }
+
+    pub fn write_ram(&mut self, address: u16, value: u8) {
+        self.mbc.write_ram(address, value);
+    }
+
+    pub fn step(&mut self, cycles: u16) {
+        self.mbc.step(cycles);
+    }
+
+    pub fn save_data(&self) -> Vec<u8> {
+        self.mbc.save_data()
+    }
+
+    pub fn load_save_data(&mut self, data: &[u8]) {
+        self.mbc.load_save_data(data);
+    }
+
+    pub fn save_timestamp_len(&self) -> usize {
+        self.mbc.save_timestamp_len()
+    }
+
+    pub fn rumble_active(&self) -> bool {
+        self.mbc.rumble_active()
+    }
+
+    pub fn take_rumble_events(&mut self) -> Vec<RumbleEvent> {
+        self.mbc.take_rumble_events()
+    }
+}
+
+impl fmt::Display for CartridgeHeader {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} [type {:02X} {:?}, ROM {} KiB, RAM {} KiB, CGB {:?}, SGB {}, licensee {}, v{}]",
+            self.title, self.cartridge_type.code, self.cartridge_type.mapper,
+            self.rom_size / 1024, self.ram_size / 1024, self.cgb_flag, self.sgb, self.licensee, self.version)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // ROM only cart of `rom_code` size with a valid header checksum
+    fn rom_image(rom_code: u8, setup: impl FnOnce(&mut [u8])) -> Vec<u8> {
+        let mut rom = vec![0; (32 * 1024) << rom_code];
+        rom[0x0148] = rom_code;
+        setup(&mut rom);
+        rom[0x014D] = header_checksum_of(&rom);
+        rom
+    }
+
+    fn parse(setup: impl FnOnce(&mut [u8])) -> Result<CartridgeHeader, CartridgeError> {
+        CartridgeHeader::parse(&rom_image(0x00, setup))
+    }
+
+    #[test]
+    fn title_and_flags() {
+        let header = parse(|rom| {
+            rom[0x0134..0x0144].copy_from_slice(b"SIXTEEN CHAR NAM");
+            rom[0x014A] = 0x01;
+            rom[0x014B] = 0x01;
+            rom[0x014C] = 0x02;
+        })
+        .unwrap();
+        assert_eq!(header.title, "SIXTEEN CHAR NAM");
+        assert_eq!(header.cgb_flag, CgbFlag::None);
+        assert!(!header.sgb);
+        assert!(!header.japanese);
+        assert_eq!(header.licensee, Licensee::Old(0x01));
+        assert_eq!(header.version, 2);
+
+        // The CGB flag takes the last title byte, NUL padding and trailing spaces are dropped
+        let header = parse(|rom| {
+            rom[0x0134..0x0143].copy_from_slice(b"POKEMON Y  \0ABC");
+            rom[0x0143] = 0xC0;
+            rom[0x0144..0x0146].copy_from_slice(b"01");
+            rom[0x0146] = 0x03;
+            rom[0x014B] = 0x33;
+        })
+        .unwrap();
+        assert_eq!(header.title, "POKEMON Y");
+        assert_eq!(header.cgb_flag, CgbFlag::Only);
+        assert!(header.sgb);
+        assert!(header.japanese);
+        assert_eq!(header.licensee, Licensee::New("01".to_string()));
+
+        let header = parse(|rom| rom[0x0143] = 0x80).unwrap();
+        assert_eq!(header.cgb_flag, CgbFlag::Compatible);
+        // SGB support needs exactly 0x03
+        assert!(!parse(|rom| rom[0x0146] = 0x01).unwrap().sgb);
+    }
+
+    #[test]
+    fn rom_size_codes() {
+        for code in 0x00..=0x08 {
+            let header = CartridgeHeader::parse(&rom_image(0x00, |rom| rom[0x0148] = code)).unwrap();
+            assert_eq!(header.rom_size, (32 * 1024) << code);
+            assert_eq!(header.rom_banks(), 2 << code);
+        }
+        for code in [0x09, 0x52, 0xFF] {
+            assert!(matches!(parse(|rom| rom[0x0148] = code), Err(CartridgeError::InvalidRomSize(c)) if c == code));
+        }
+    }
+
+    #[test]
+    fn ram_size_codes() {
+        let sizes = [(0x00, 0, 0), (0x01, 2 * 1024, 1), (0x02, 8 * 1024, 1), (0x03, 32 * 1024, 4), (0x04, 128 * 1024, 16), (0x05, 64 * 1024, 8)];
+        for (code, size, banks) in sizes {
+            let header = parse(|rom| rom[0x0149] = code).unwrap();
+            assert_eq!((header.ram_size, header.ram_banks()), (size, banks), "code {:#04X}", code);
+        }
+        assert!(matches!(parse(|rom| rom[0x0149] = 0x06), Err(CartridgeError::InvalidRamSize(0x06))));
+    }
+
+    #[test]
+    fn cartridge_types() {
+        let header = parse(|rom| rom[0x0147] = 0x10).unwrap();
+        let cartridge_type = header.cartridge_type;
+        assert_eq!(cartridge_type.mapper, Mapper::Mbc3);
+        assert!(cartridge_type.ram && cartridge_type.battery && cartridge_type.rtc && !cartridge_type.rumble);
+        assert!(matches!(parse(|rom| rom[0x0147] = 0x04), Err(CartridgeError::UnsupportedCartridgeType(0x04))));
+    }
+
+    #[test]
+    fn truncated_files() {
+        let rom = rom_image(0x00, |_| {});
+        assert!(matches!(
+            CartridgeHeader::parse(&rom[..0x014F]),
+            Err(CartridgeError::Truncated { expected: 0x0150, actual: 0x014F })
+        ));
+        // The header parses, but the file is shorter than the declared 64 KiB
+        let rom = rom_image(0x01, |_| {});
+        assert!(matches!(
+            Cartridge::from_bytes(rom[..0x8000].to_vec()),
+            Err(CartridgeError::Truncated { expected: 0x10000, actual: 0x8000 })
+        ));
+    }
+
+    #[test]
+    fn header_checksum() {
+        let mut rom = rom_image(0x00, |rom| rom[0x0134..0x0138].copy_from_slice(b"TEST"));
+        assert_eq!(CartridgeHeader::parse(&rom).unwrap().header_checksum, rom[0x014D]);
+
+        let expected = rom[0x014D];
+        rom[0x0134] = b'B';
+        assert!(matches!(
+            CartridgeHeader::parse(&rom),
+            Err(CartridgeError::HeaderChecksum { expected: e, actual: a }) if e == expected && a == expected.wrapping_add(b'T' - b'B')
+        ));
+    }
+
+    #[test]
+    fn global_checksum() {
+        assert_eq!(global_checksum_of(&[0xFF; 0x0150]), (0xFF * (0x0150 - 2)) as u16);
+        // The checksum bytes themselves are skipped
+        let mut rom = vec![0; 0x0150];
+        rom[0x014E] = 0x12;
+        rom[0x014F] = 0x34;
+        assert_eq!(global_checksum_of(&rom), 0);
+
+        let mut rom = rom_image(0x00, |rom| rom[0x0200] = 0x42);
+        let checksum = global_checksum_of(&rom);
+        rom[0x014E..0x0150].copy_from_slice(&checksum.to_be_bytes());
+        let cartridge = Cartridge::from_bytes(rom.clone()).unwrap();
+        assert_eq!(cartridge.header.global_checksum, checksum);
+        assert!(cartridge.global_checksum_valid());
+
+        rom[0x0201] = 1;
+        assert!(!Cartridge::from_bytes(rom).unwrap().global_checksum_valid());
+    }
+
+    #[test]
+    fn extra_bytes_past_the_declared_size_are_ignored() {
+        let mut rom = rom_image(0x00, |rom| rom[0x7FFF] = 0x42);
+        let checksum = global_checksum_of(&rom);
+        rom[0x014E..0x0150].copy_from_slice(&checksum.to_be_bytes());
+        rom.extend_from_slice(&[0xFF; 0x8000]);
+
+        let cartridge = Cartridge::from_bytes(rom).unwrap();
+        assert_eq!(cartridge.read_rom(0x7FFF), 0x42);
+        assert!(cartridge.global_checksum_valid());
+    }
+
+    #[test]
+    fn rom_only_carts_are_limited_to_32_kib() {
+        let rom = rom_image(0x01, |_| {});
+        assert!(matches!(
+            Cartridge::from_bytes(rom),
+            Err(CartridgeError::SizeMismatch { expected: 0x8000, actual: 0x10000 })
+        ));
+    }
+}
@@ -1,3 +1,4 @@
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum HardwareRegister {
     // Joypad
@@ -74,6 +75,10 @@ pub enum HardwareRegister {
     // CGB Registers
     KEY1 = 0xFF4D,
     VBK = 0xFF4F,
+
+    // Boot ROM mapping control
+    BANK = 0xFF50,
+
     HDMA1 = 0xFF51,
     HDMA2 = 0xFF52,
     HDMA3 = 0xFF53,
@@ -0,0 +1,444 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::GameBoy;
+use crate::cartridge::{RAM_BANK_SIZE, ROM_BANK_SIZE};
+use crate::data::HardwareRegister;
+use crate::interrupts::Interrupt;
+use crate::mbc::{MemoryBankController, read_rom_bank};
+use crate::model::Model;
+
+// GBS (Game Boy Sound System) header, 0x70 bytes, all words little endian
+// 00-02	"GBS"
+// 03	Version (1)
+// 04	Number of songs
+// 05	First song (1 based)
+// 06-07	Load address (0400-7FFF)
+// 08-09	Init address, called with the song number (0 based) in A
+// 0A-0B	Play address, called at the play rate
+// 0C-0D	Stack pointer
+// 0E	Timer modulo (TMA)
+// 0F	Timer control (TAC), bit 2 set plays on the timer instead of VBlank
+// 10-2F	Title
+// 30-4F	Author
+// 50-6F	Copyright
+// The music code follows and is loaded at the load address.
+
+const HEADER_SIZE: usize = 0x70;
+const MIN_LOAD_ADDRESS: u16 = 0x0400;
+
+// The player maps the music code through GbsMapper. Bank 0 below the load
+// address holds the RST redirects, the interrupt handlers calling play and an idle loop.
+const IDLE_ADDRESS: u16 = 0x0068;
+const MAX_BANKS: usize = 256;
+
+const TAC_ENABLE: u8 = 0b0000_0100;
+const LCDC_ENABLE: u8 = 0b1000_0000;
+
+#[derive(Debug)]
+pub enum GbsError {
+    Io(io::Error),
+    Truncated(usize),
+    InvalidMagic,
+    UnsupportedVersion(u8),
+    InvalidLoadAddress(u16),
+    TooLarge(usize),
+    InvalidSong { song: u8, count: u8 },
+}
+
+impl fmt::Display for GbsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GbsError::Io(err) => write!(f, "failed to read GBS file: {}", err),
+            GbsError::Truncated(len) => write!(f, "GBS file is truncated: {:#X} bytes", len),
+            GbsError::InvalidMagic => write!(f, "not a GBS file"),
+            GbsError::UnsupportedVersion(version) => write!(f, "unsupported GBS version {}", version),
+            GbsError::InvalidLoadAddress(address) => write!(f, "invalid load address {:#06X}", address),
+            GbsError::TooLarge(len) => write!(f, "GBS data doesn't fit in 4 MiB of ROM: {:#X} bytes", len),
+            GbsError::InvalidSong { song, count } => write!(f, "song {} out of range, the file has {} songs", song, count),
+        }
+    }
+}
+
+impl std::error::Error for GbsError {}
+
+impl From<io::Error> for GbsError {
+    fn from(err: io::Error) -> Self {
+        GbsError::Io(err)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct GbsHeader {
+    pub song_count: u8,
+    pub first_song: u8,
+    pub load_address: u16,
+    pub init_address: u16,
+    pub play_address: u16,
+    pub stack_pointer: u16,
+    pub timer_modulo: u8,
+    pub timer_control: u8,
+    pub title: String,
+    pub author: String,
+    pub copyright: String,
+}
+
+impl GbsHeader {
+    pub fn parse(data: &[u8]) -> Result<Self, GbsError> {
+        if data.len() < HEADER_SIZE {
+            return Err(GbsError::Truncated(data.len()));
+        }
+        if &data[0..3] != b"GBS" {
+            return Err(GbsError::InvalidMagic);
+        }
+        if data[3] != 1 {
+            return Err(GbsError::UnsupportedVersion(data[3]));
+        }
+
+        let word = |offset: usize| u16::from_le_bytes([data[offset], data[offset + 1]]);
+        let load_address = word(0x06);
+        if !(MIN_LOAD_ADDRESS..0x8000).contains(&load_address) {
+            return Err(GbsError::InvalidLoadAddress(load_address));
+        }
+
+        Ok(GbsHeader {
+            song_count: data[0x04],
+            first_song: data[0x05].max(1),
+            load_address,
+            init_address: word(0x08),
+            play_address: word(0x0A),
+            stack_pointer: word(0x0C),
+            timer_modulo: data[0x0E],
+            timer_control: data[0x0F],
+            title: text_field(&data[0x10..0x30]),
+            author: text_field(&data[0x30..0x50]),
+            copyright: text_field(&data[0x50..0x70]),
+        })
+    }
+
+    // Play is called on timer overflow when TAC enables the timer, otherwise on VBlank
+    pub fn uses_timer(&self) -> bool {
+        self.timer_control & TAC_ENABLE != 0
+    }
+}
+
+impl fmt::Display for GbsHeader {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Rips often leave author and copyright empty
+        let mut name = [self.title.as_str(), self.author.as_str()]
+            .into_iter()
+            .filter(|field| !field.is_empty())
+            .collect::<Vec<_>>()
+            .join(" - ");
+        if !self.copyright.is_empty() {
+            if !name.is_empty() {
+                name.push(' ');
+            }
+            name.push_str(&format!("({})", self.copyright));
+        }
+        if name.is_empty() {
+            name.push_str("Untitled");
+        }
+        write!(f, "{}, {} songs, play on {}", name, self.song_count, if self.uses_timer() { "timer" } else { "VBlank" })
+    }
+}
+
+fn text_field(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .take_while(|&&byte| byte != 0)
+        .map(|&byte| if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '?' })
+        .collect::<String>()
+        .trim_end()
+        .to_string()
+}
+
+pub struct Gbs {
+    pub header: GbsHeader,
+    code: Vec<u8>,
+}
+
+impl Gbs {
+    pub fn from_bytes(data: Vec<u8>) -> Result<Self, GbsError> {
+        let header = GbsHeader::parse(&data)?;
+        let code = data[HEADER_SIZE..].to_vec();
+        let end = header.load_address as usize + code.len();
+        if end > ROM_BANK_SIZE * MAX_BANKS {
+            return Err(GbsError::TooLarge(code.len()));
+        }
+        Ok(Gbs { header, code })
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, GbsError> {
+        Gbs::from_bytes(fs::read(path)?)
+    }
+
+    // Builds the ROM the music code runs from. RST n jumps to load address + n,
+    // the VBlank and timer vectors call play, and init returns to an idle loop
+    // that waits for the next interrupt.
+    fn rom_image(&self) -> Vec<u8> {
+        let load = self.header.load_address;
+        let end = load as usize + self.code.len();
+        let mut rom = vec![0xFF; end.div_ceil(ROM_BANK_SIZE).max(2) * ROM_BANK_SIZE];
+        rom[load as usize..end].copy_from_slice(&self.code);
+
+        for vector in (0x00..=0x38).step_by(8) {
+            let [low, high] = (load + vector).to_le_bytes();
+            rom[vector as usize..vector as usize + 3].copy_from_slice(&[0xC3, low, high]); // JP
+        }
+        let [low, high] = self.header.play_address.to_le_bytes();
+        for vector in [Interrupt::VBlank as usize, Interrupt::Timer as usize] {
+            rom[vector..vector + 4].copy_from_slice(&[0xCD, low, high, 0xD9]); // CALL play, RETI
+        }
+        // EI, HALT, NOP, JR idle
+        let idle = IDLE_ADDRESS as usize;
+        rom[idle..idle + 5].copy_from_slice(&[0xFB, 0x76, 0x00, 0x18, 0xFB]);
+        rom
+    }
+
+    // Loads the music into `gameboy` and points the CPU at init for `song`
+    // (1 based). From then on the normal run loop calls play at the file's play rate.
+    pub fn start(&self, gameboy: &mut GameBoy, song: u8, model: Model) -> Result<(), GbsError> {
+        if song == 0 || song > self.header.song_count {
+            return Err(GbsError::InvalidSong { song, count: self.header.song_count });
+        }
+
+        gameboy.memory.load_mapper(Box::new(GbsMapper::new(self.rom_image())));
+        gameboy.apply_post_boot_state(model);
+
+        let memory = &mut gameboy.memory;
+        memory.write_hardware_register(HardwareRegister::TMA, self.header.timer_modulo);
+        memory.write_hardware_register(HardwareRegister::TIMA, self.header.timer_modulo);
+        // Double speed (bit 7) isn't emulated, only the timer part of TAC is used
+        memory.write_hardware_register(HardwareRegister::TAC, self.header.timer_control & 0b111);
+        memory.write_hardware_register(HardwareRegister::IF, 0);
+        if self.header.uses_timer() {
+            memory.write_hardware_register(HardwareRegister::IE, Interrupt::Timer.mask());
+        } else {
+            memory.write_hardware_register(HardwareRegister::LCDC, LCDC_ENABLE);
+            memory.write_hardware_register(HardwareRegister::IE, Interrupt::VBlank.mask());
+        }
+
+        // Call init with the idle loop as the return address
+        let cpu = &mut gameboy.cpu;
+        cpu.sp = self.header.stack_pointer.wrapping_sub(2);
+        memory.write_word(cpu.sp, IDLE_ADDRESS);
+        cpu.a = song - 1;
+        cpu.pc = self.header.init_address;
+        cpu.interrupts.ime = false;
+        Ok(())
+    }
+}
+
+// GBS files only rely on a ROM bank register at 2000-3FFF, with 0 selecting
+// bank 1 like on MBC1, and 8 KiB of RAM at A000-BFFF that is always enabled
+pub struct GbsMapper {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    rom_bank: u8,
+}
+
+impl GbsMapper {
+    pub fn new(rom: Vec<u8>) -> Self {
+        GbsMapper { rom, ram: vec![0; RAM_BANK_SIZE], rom_bank: 1 }
+    }
+}
+
+impl MemoryBankController for GbsMapper {
+    fn read_rom(&self, address: u16) -> u8 {
+        match address {
+            0x0000..=0x3FFF => read_rom_bank(&self.rom, 0, address),
+            _ => read_rom_bank(&self.rom, self.rom_bank as usize, address),
+        }
+    }
+
+    fn write_rom(&mut self, address: u16, value: u8) {
+        if (0x2000..=0x3FFF).contains(&address) {
+            self.rom_bank = value.max(1);
+        }
+    }
+
+    fn read_ram(&self, address: u16) -> u8 {
+        self.ram[address as usize & (RAM_BANK_SIZE - 1)]
+    }
+
+    fn write_ram(&mut self, address: u16, value: u8) {
+        self.ram[address as usize & (RAM_BANK_SIZE - 1)] = value;
+    }
+
+    // Nothing is battery backed
+    fn save_data(&self) -> Vec<u8> {
+        Vec::new()
+    }
+
+    fn load_save_data(&mut self, _data: &[u8]) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CYCLES_PER_FRAME;
+
+    // init stores the song number at C000, play counts its calls at C001
+    const CODE: [u8; 12] = [0xEA, 0x00, 0xC0, 0xC9, 0xFA, 0x01, 0xC0, 0x3C, 0xEA, 0x01, 0xC0, 0xC9];
+
+    fn gbs_file(timer_control: u8, code: &[u8]) -> Vec<u8> {
+        let mut data = vec![0; HEADER_SIZE];
+        data[0..3].copy_from_slice(b"GBS");
+        data[0x03] = 1;
+        data[0x04] = 3;
+        data[0x05] = 2;
+        data[0x06..0x08].copy_from_slice(&0x0400u16.to_le_bytes());
+        data[0x08..0x0A].copy_from_slice(&0x0400u16.to_le_bytes());
+        data[0x0A..0x0C].copy_from_slice(&0x0404u16.to_le_bytes());
+        data[0x0C..0x0E].copy_from_slice(&0xFFFEu16.to_le_bytes());
+        data[0x0E] = 0x00;
+        data[0x0F] = timer_control;
+        data[0x10..0x14].copy_from_slice(b"Tune");
+        data[0x30..0x33].copy_from_slice(b"Bob");
+        data[0x50..0x54].copy_from_slice(b"1999");
+        data.extend_from_slice(code);
+        data
+    }
+
+    #[test]
+    fn parses_the_header() {
+        let header = GbsHeader::parse(&gbs_file(0x00, &CODE)).unwrap();
+        assert_eq!(header.song_count, 3);
+        assert_eq!(header.first_song, 2);
+        assert_eq!(header.load_address, 0x0400);
+        assert_eq!(header.init_address, 0x0400);
+        assert_eq!(header.play_address, 0x0404);
+        assert_eq!(header.stack_pointer, 0xFFFE);
+        assert_eq!((header.title.as_str(), header.author.as_str(), header.copyright.as_str()), ("Tune", "Bob", "1999"));
+    }
+
+    #[test]
+    fn rejects_bad_magic_version_and_length() {
+        let mut data = gbs_file(0x00, &CODE);
+        data[0] = b'X';
+        assert!(matches!(GbsHeader::parse(&data), Err(GbsError::InvalidMagic)));
+
+        let mut data = gbs_file(0x00, &CODE);
+        data[0x03] = 2;
+        assert!(matches!(GbsHeader::parse(&data), Err(GbsError::UnsupportedVersion(2))));
+
+        let data = gbs_file(0x00, &CODE);
+        assert!(matches!(GbsHeader::parse(&data[..HEADER_SIZE - 1]), Err(GbsError::Truncated(_))));
+    }
+
+    #[test]
+    fn load_address_range() {
+        for (address, valid) in [(0x0000, false), (0x03FF, false), (0x0400, true), (0x7FFF, true), (0x8000, false)] {
+            let mut data = gbs_file(0x00, &CODE);
+            data[0x06..0x08].copy_from_slice(&u16::to_le_bytes(address));
+            match GbsHeader::parse(&data) {
+                Ok(header) => assert!(valid && header.load_address == address, "{:#06X}", address),
+                Err(GbsError::InvalidLoadAddress(bad)) => assert!(!valid && bad == address, "{:#06X}", address),
+                Err(err) => panic!("{:#06X}: {}", address, err),
+            }
+        }
+    }
+
+    #[test]
+    fn first_song_is_one_based() {
+        let mut data = gbs_file(0x00, &CODE);
+        data[0x05] = 0;
+        assert_eq!(GbsHeader::parse(&data).unwrap().first_song, 1);
+        data[0x05] = 1;
+        assert_eq!(GbsHeader::parse(&data).unwrap().first_song, 1);
+    }
+
+    #[test]
+    fn timer_or_vblank() {
+        assert!(!GbsHeader::parse(&gbs_file(0x00, &CODE)).unwrap().uses_timer());
+        assert!(!GbsHeader::parse(&gbs_file(0x83, &CODE)).unwrap().uses_timer());
+        assert!(GbsHeader::parse(&gbs_file(0x04, &CODE)).unwrap().uses_timer());
+        assert!(GbsHeader::parse(&gbs_file(0x87, &CODE)).unwrap().uses_timer());
+    }
+
+    #[test]
+    fn display_skips_empty_fields() {
+        let mut header = GbsHeader::parse(&gbs_file(0x00, &CODE)).unwrap();
+        assert_eq!(header.to_string(), "Tune - Bob (1999), 3 songs, play on VBlank");
+        header.copyright.clear();
+        assert_eq!(header.to_string(), "Tune - Bob, 3 songs, play on VBlank");
+        header.author.clear();
+        assert_eq!(header.to_string(), "Tune, 3 songs, play on VBlank");
+        header.copyright = "1999".to_string();
+        assert_eq!(header.to_string(), "Tune (1999), 3 songs, play on VBlank");
+        header.title.clear();
+        header.copyright.clear();
+        header.timer_control = 0x04;
+        assert_eq!(header.to_string(), "Untitled, 3 songs, play on timer");
+    }
+
+    #[test]
+    fn rejects_code_past_4_mib() {
+        let code = vec![0; ROM_BANK_SIZE * MAX_BANKS - 0x0400 + 1];
+        assert!(matches!(Gbs::from_bytes(gbs_file(0x00, &code)), Err(GbsError::TooLarge(_))));
+    }
+
+    #[test]
+    fn bank_zero_selects_bank_one() {
+        // Marks the first byte of banks 1 and 2
+        let mut code = vec![0; 2 * ROM_BANK_SIZE - 0x0400 + 1];
+        code[ROM_BANK_SIZE - 0x0400] = 0x11;
+        code[2 * ROM_BANK_SIZE - 0x0400] = 0x22;
+        let gbs = Gbs::from_bytes(gbs_file(0x00, &code)).unwrap();
+        let mut mapper = GbsMapper::new(gbs.rom_image());
+
+        assert_eq!(mapper.read_rom(0x4000), 0x11);
+        mapper.write_rom(0x2000, 2);
+        assert_eq!(mapper.read_rom(0x4000), 0x22);
+        assert_eq!(mapper.read_rom(0x0400), 0x00);
+        mapper.write_rom(0x3FFF, 0);
+        assert_eq!(mapper.read_rom(0x4000), 0x11);
+        // Writes elsewhere don't touch the bank
+        mapper.write_rom(0x4000, 2);
+        assert_eq!(mapper.read_rom(0x4000), 0x11);
+
+        mapper.write_ram(0xA123, 0x5A);
+        assert_eq!(mapper.read_ram(0xA123), 0x5A);
+    }
+
+    #[test]
+    fn start_rejects_songs_out_of_range() {
+        let gbs = Gbs::from_bytes(gbs_file(0x00, &CODE)).unwrap();
+        for song in [0, 4] {
+            let result = gbs.start(&mut GameBoy::new(), song, Model::Dmg);
+            assert!(matches!(result, Err(GbsError::InvalidSong { count: 3, .. })), "song {}", song);
+        }
+    }
+
+    // Runs song 2 for `cycles` cycles, returns the song INIT saw and how many
+    // times PLAY ran
+    fn play_calls(timer_modulo: u8, timer_control: u8, cycles: u64) -> (u8, u8) {
+        let mut data = gbs_file(timer_control, &CODE);
+        data[0x0E] = timer_modulo;
+        let gbs = Gbs::from_bytes(data).unwrap();
+        let mut gameboy = GameBoy::new();
+        gbs.start(&mut gameboy, 3, Model::Dmg).unwrap();
+        assert!(gameboy.memory.cartridge().is_none());
+        gameboy.run_cycles(cycles);
+        (gameboy.memory.read_byte(0xC000), gameboy.memory.read_byte(0xC001))
+    }
+
+    #[test]
+    fn plays_on_vblank() {
+        // Five frames, the first may start partway through
+        let (song, calls) = play_calls(0x00, 0x00, 5 * CYCLES_PER_FRAME as u64);
+        assert_eq!(song, 2);
+        assert!((4..=5).contains(&calls), "{} calls", calls);
+    }
+
+    #[test]
+    fn plays_on_the_timer() {
+        // 4096 Hz with TMA 0xFC overflows every 4 ticks of 1024 cycles
+        let (song, calls) = play_calls(0xFC, 0x04, 5 * 4096);
+        assert_eq!(song, 2);
+        assert!((4..=5).contains(&calls), "{} calls", calls);
+    }
+}
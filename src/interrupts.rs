@@ -1,6 +1,8 @@
 use crate::cpu::CPU;
 use crate::memory::Memory;
+use crate::data::HardwareRegister;
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Interrupt {
     VBlank = 0x40,
     LCDStat = 0x48,
@@ -9,6 +11,18 @@ pub enum Interrupt {
     Joypad = 0x60,
 }
 
+impl Interrupt {
+    // Bit in IE/IF
+    pub fn mask(self) -> u8 {
+        1 << ((self as u16 - Interrupt::VBlank as u16) / 8)
+    }
+}
+
+pub fn request_interrupt(memory: &mut Memory, interrupt: Interrupt) {
+    let if_ = memory.read_hardware_register(HardwareRegister::IF);
+    memory.write_hardware_register(HardwareRegister::IF, if_ | interrupt.mask());
+}
+
 pub fn handle_interrupt(cpu: &mut CPU, memory: &mut Memory, interrupt: u8) {
     let addr = match interrupt {
         0 => Interrupt::VBlank as u16, // V-Blank
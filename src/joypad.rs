@@ -0,0 +1,150 @@
+// P1 (0xFF00)
+// Bit 5	Select buttons (0 = selected)
+// Bit 4	Select d-pad (0 = selected)
+// Bit 3	Start / Down (0 = pressed)
+// Bit 2	Select / Up
+// Bit 1	B / Left
+// Bit 0	A / Right
+
+const SELECT_BUTTONS: u8 = 0b0010_0000;
+const SELECT_DPAD: u8 = 0b0001_0000;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Buttons {
+    pub right: bool,
+    pub left: bool,
+    pub up: bool,
+    pub down: bool,
+    pub a: bool,
+    pub b: bool,
+    pub select: bool,
+    pub start: bool,
+}
+
+impl Buttons {
+    // Active high nibbles in P1 bit order
+    fn dpad_bits(&self) -> u8 {
+        (self.right as u8) | (self.left as u8) << 1 | (self.up as u8) << 2 | (self.down as u8) << 3
+    }
+
+    fn button_bits(&self) -> u8 {
+        (self.a as u8) | (self.b as u8) << 1 | (self.select as u8) << 2 | (self.start as u8) << 3
+    }
+}
+
+pub struct Joypad {
+    select: u8,
+    buttons: Buttons,
+}
+
+impl Joypad {
+    pub fn new() -> Self {
+        Joypad { select: SELECT_BUTTONS | SELECT_DPAD, buttons: Buttons::default() }
+    }
+
+    pub fn read(&self) -> u8 {
+        0xC0 | self.select | self.lines()
+    }
+
+    // Returns true if the write pulled any input line low, which requests the joypad interrupt
+    pub fn write(&mut self, value: u8) -> bool {
+        let before = self.lines();
+        self.select = value & (SELECT_BUTTONS | SELECT_DPAD);
+        falling_edge(before, self.lines())
+    }
+
+    // Returns true if any input line went from high to low
+    pub fn set_buttons(&mut self, buttons: Buttons) -> bool {
+        let before = self.lines();
+        self.buttons = buttons;
+        falling_edge(before, self.lines())
+    }
+
+    // A selected key is held, this is what wakes the CPU from STOP
+    pub fn any_line_low(&self) -> bool {
+        self.lines() != 0x0F
+    }
+
+    // Active low input lines P10-P13, a selected group pulls its pressed keys low
+    fn lines(&self) -> u8 {
+        let mut pressed = 0;
+        if self.select & SELECT_DPAD == 0 {
+            pressed |= self.buttons.dpad_bits();
+        }
+        if self.select & SELECT_BUTTONS == 0 {
+            pressed |= self.buttons.button_bits();
+        }
+        !pressed & 0x0F
+    }
+}
+
+fn falling_edge(before: u8, after: u8) -> bool {
+    before & !after != 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interrupts::Interrupt;
+    use crate::memory::Memory;
+
+    #[test]
+    fn nothing_selected_reads_all_high() {
+        let mut joypad = Joypad::new();
+        joypad.set_buttons(Buttons { a: true, down: true, ..Buttons::default() });
+        assert_eq!(joypad.read(), 0xFF);
+        assert!(!joypad.any_line_low());
+    }
+
+    #[test]
+    fn selected_group_pulls_its_keys_low() {
+        let mut joypad = Joypad::new();
+        joypad.set_buttons(Buttons { right: true, down: true, a: true, select: true, ..Buttons::default() });
+
+        joypad.write(SELECT_BUTTONS);
+        assert_eq!(joypad.read(), 0xC0 | SELECT_BUTTONS | 0b0110);
+        joypad.write(SELECT_DPAD);
+        assert_eq!(joypad.read(), 0xC0 | SELECT_DPAD | 0b1010);
+        // Both groups selected share the lines
+        joypad.write(0x00);
+        assert_eq!(joypad.read(), 0xC0 | 0b0010);
+        assert!(joypad.any_line_low());
+    }
+
+    #[test]
+    fn only_the_select_bits_are_writable() {
+        let mut joypad = Joypad::new();
+        joypad.write(0xCF);
+        assert_eq!(joypad.read(), 0xCF);
+        joypad.write(0x3F);
+        assert_eq!(joypad.read(), 0xFF);
+    }
+
+    #[test]
+    fn interrupt_on_falling_edges_only() {
+        let mut joypad = Joypad::new();
+        joypad.write(SELECT_DPAD);
+        // A d-pad key while only the buttons are selected doesn't change a line
+        assert!(!joypad.set_buttons(Buttons { up: true, ..Buttons::default() }));
+        assert!(joypad.set_buttons(Buttons { up: true, b: true, ..Buttons::default() }));
+        assert!(!joypad.set_buttons(Buttons { up: true, b: true, ..Buttons::default() }));
+        assert!(!joypad.set_buttons(Buttons::default()));
+
+        // Selecting a group with a key already held is an edge too
+        joypad.set_buttons(Buttons { down: true, ..Buttons::default() });
+        assert!(joypad.write(SELECT_BUTTONS));
+        assert!(!joypad.write(SELECT_BUTTONS));
+        assert!(!joypad.write(SELECT_BUTTONS | SELECT_DPAD));
+    }
+
+    #[test]
+    fn p1_write_requests_the_joypad_interrupt() {
+        let mut memory = Memory::new();
+        memory.set_buttons(Buttons { b: true, ..Buttons::default() });
+        memory.write_byte(0xFF00, SELECT_BUTTONS);
+        assert_eq!(memory.read_byte(0xFF0F) & Interrupt::Joypad.mask(), 0);
+        memory.write_byte(0xFF00, SELECT_DPAD);
+        assert_eq!(memory.read_byte(0xFF00), 0xC0 | SELECT_DPAD | 0b1101);
+        assert_ne!(memory.read_byte(0xFF0F) & Interrupt::Joypad.mask(), 0);
+    }
+}
@@ -0,0 +1,301 @@
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+#[cfg(unix)]
+use std::path::Path;
+use std::time::Duration;
+
+use crate::{CYCLES_PER_FRAME, GameBoy};
+use crate::serial::SerialDevice;
+
+// Link cable between two emulator instances.
+//
+// Both sides run in lockstep on a fixed quantum of emulated cycles. At the end
+// of every quantum each side sends one frame and blocks until it has the
+// peer's frame for the same quantum, so neither can run more than a quantum
+// ahead and every byte lands on the same emulated cycle on every run, however
+// the host schedules the two instances.
+//
+// Handshake, sent by both sides on connect:
+//   "RBLK", protocol version (u8), role (u8, 0 = host, 1 = guest), quantum (u32 LE)
+// The host's quantum wins.
+//
+// Frame, sent by both sides at every quantum boundary:
+//   flags (u8, bit 0 = this side drives the clock), data (u8), SB (u8)
+// A side drives the clock when its own transfer with the internal clock started
+// during the quantum, `data` is the byte it shifted out. The other side answers
+// with the SB it had at the boundary. If both drive the clock neither sees the
+// other's clock pulses and both read 0xFF.
+
+const MAGIC: &[u8; 4] = b"RBLK";
+const PROTOCOL_VERSION: u8 = 1;
+const FLAG_CLOCK: u8 = 0b0000_0001;
+
+// One serial byte at the normal 8192 Hz clock
+pub const DEFAULT_QUANTUM: u32 = 4096;
+
+// A peer that sends nothing for this long is treated as unplugged
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkRole {
+    Host,
+    Guest,
+}
+
+trait Stream: Read + Write + Send {}
+impl<T: Read + Write + Send> Stream for T {}
+
+pub struct LinkCable {
+    stream: Option<Box<dyn Stream>>,
+    quantum: u32,
+    cycles: u32,
+    outgoing: Option<u8>, // our clocked transfer waiting for the next boundary
+    resolved: Option<u8>, // reply to it, handed out by the next exchange()
+}
+
+impl LinkCable {
+    fn handshake<S: Stream + 'static>(mut stream: S, role: LinkRole, quantum: u32) -> io::Result<Self> {
+        let mut hello = [0u8; 10];
+        hello[0..4].copy_from_slice(MAGIC);
+        hello[4] = PROTOCOL_VERSION;
+        hello[5] = if role == LinkRole::Host { 0 } else { 1 };
+        hello[6..10].copy_from_slice(&quantum.to_le_bytes());
+        stream.write_all(&hello)?;
+        stream.flush()?;
+
+        let mut peer = [0u8; 10];
+        stream.read_exact(&mut peer)?;
+        if &peer[0..4] != MAGIC || peer[4] != PROTOCOL_VERSION {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "peer is not a compatible link cable"));
+        }
+        let peer_is_host = peer[5] == 0;
+        if peer_is_host == (role == LinkRole::Host) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "both ends of the link claim the same role"));
+        }
+        let peer_quantum = u32::from_le_bytes([peer[6], peer[7], peer[8], peer[9]]);
+        let quantum = if role == LinkRole::Host { quantum } else { peer_quantum };
+        if quantum == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "link quantum must be non zero"));
+        }
+
+        Ok(LinkCable {
+            stream: Some(Box::new(stream)),
+            quantum,
+            cycles: 0,
+            outgoing: None,
+            resolved: None,
+        })
+    }
+
+    fn tcp(stream: TcpStream, role: LinkRole) -> io::Result<Self> {
+        stream.set_nodelay(true)?;
+        stream.set_read_timeout(Some(READ_TIMEOUT))?;
+        LinkCable::handshake(stream, role, DEFAULT_QUANTUM)
+    }
+
+    #[cfg(unix)]
+    fn unix(stream: UnixStream, role: LinkRole) -> io::Result<Self> {
+        stream.set_read_timeout(Some(READ_TIMEOUT))?;
+        LinkCable::handshake(stream, role, DEFAULT_QUANTUM)
+    }
+
+    // Waits for one guest to connect
+    pub fn listen_tcp<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        let (stream, _) = listener.accept()?;
+        LinkCable::tcp(stream, LinkRole::Host)
+    }
+
+    pub fn connect_tcp<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+        LinkCable::tcp(TcpStream::connect(addr)?, LinkRole::Guest)
+    }
+
+    #[cfg(unix)]
+    pub fn listen_unix<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let listener = UnixListener::bind(path)?;
+        let (stream, _) = listener.accept()?;
+        LinkCable::unix(stream, LinkRole::Host)
+    }
+
+    #[cfg(unix)]
+    pub fn connect_unix<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        LinkCable::unix(UnixStream::connect(path)?, LinkRole::Guest)
+    }
+
+    // Both ends of a cable for linking two instances in one process, over loopback TCP
+    pub fn pair() -> io::Result<(LinkCable, LinkCable)> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+        let guest = std::thread::spawn(move || LinkCable::connect_tcp(addr));
+        let (stream, _) = listener.accept()?;
+        let host = LinkCable::tcp(stream, LinkRole::Host)?;
+        let guest = guest.join().map_err(|_| io::Error::other("link guest thread panicked"))??;
+        Ok((host, guest))
+    }
+
+    // Exchanges frames with the peer for the quantum that just ended. Returns
+    // the byte the peer clocked into us, if it drove the clock.
+    fn sync(&mut self, sb: u8) -> io::Result<Option<u8>> {
+        let Some(stream) = &mut self.stream else {
+            return Ok(None);
+        };
+
+        let clocking = self.outgoing.is_some();
+        let frame = [if clocking { FLAG_CLOCK } else { 0 }, self.outgoing.unwrap_or(0xFF), sb];
+        stream.write_all(&frame)?;
+        stream.flush()?;
+
+        let mut peer = [0u8; 3];
+        stream.read_exact(&mut peer).map_err(|err| match err.kind() {
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => io::Error::new(io::ErrorKind::TimedOut, "peer stopped responding"),
+            _ => err,
+        })?;
+        let peer_clocking = peer[0] & FLAG_CLOCK != 0;
+
+        if clocking {
+            self.outgoing = None;
+            self.resolved = Some(if peer_clocking { 0xFF } else { peer[2] });
+            return Ok(None);
+        }
+        Ok(if peer_clocking { Some(peer[1]) } else { None })
+    }
+
+    fn disconnect(&mut self, err: io::Error) {
+        eprintln!("Link cable disconnected: {}", err);
+        self.stream = None;
+        // Finish anything in flight as if the cable had been pulled
+        if self.outgoing.take().is_some() {
+            self.resolved = Some(0xFF);
+        }
+    }
+}
+
+impl SerialDevice for LinkCable {
+    fn exchange(&mut self, outgoing: u8) -> Option<u8> {
+        if let Some(incoming) = self.resolved.take() {
+            return Some(incoming);
+        }
+        if self.stream.is_none() {
+            return Some(0xFF);
+        }
+        // Sent at the next boundary, stall until the peer has answered
+        self.outgoing = Some(outgoing);
+        None
+    }
+
+    fn step(&mut self, cycles: u16, outgoing: u8, _waiting: bool) -> Option<u8> {
+        self.cycles += cycles as u32;
+        let mut incoming = None;
+        while self.cycles >= self.quantum {
+            self.cycles -= self.quantum;
+            match self.sync(outgoing) {
+                Ok(Some(byte)) => incoming = Some(byte),
+                Ok(None) => {}
+                Err(err) => self.disconnect(err),
+            }
+        }
+        incoming
+    }
+}
+
+// Runs two linked instances side by side for `frames` frames worth of cycles.
+// Each instance gets its own thread since both block on the other at every
+// quantum. Both run the same whole number of quanta, so they finish on the same
+// sync and neither is left waiting for a frame that never comes.
+pub fn run_linked(a: &mut GameBoy, b: &mut GameBoy, frames: u32) -> io::Result<()> {
+    let (host, guest) = LinkCable::pair()?;
+    let quanta = (frames as u64 * CYCLES_PER_FRAME as u64).div_ceil(host.quantum as u64);
+    let cycles = quanta * host.quantum as u64;
+    a.attach_serial_device(Box::new(host));
+    b.attach_serial_device(Box::new(guest));
+
+    std::thread::scope(|scope| {
+        scope.spawn(|| {
+            a.run_cycles(cycles);
+            a.serial.detach();
+        });
+        scope.spawn(|| {
+            b.run_cycles(cycles);
+            b.serial.detach();
+        });
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::HardwareRegister;
+    use crate::model::Model;
+
+    // Loads SB with `sb`, starts a transfer with the given SC and spins
+    fn transfer_program(sb: u8, sc: u8) -> Vec<u8> {
+        vec![0x3E, sb, 0xE0, 0x01, 0x3E, sc, 0xE0, 0x02, 0x18, 0xFE]
+    }
+
+    fn gameboy_running(program: &[u8]) -> GameBoy {
+        let mut gameboy = GameBoy::new();
+        gameboy.apply_post_boot_state(Model::Dmg);
+        for (i, &byte) in program.iter().enumerate() {
+            gameboy.memory.write_byte(0x0100 + i as u16, byte);
+        }
+        gameboy
+    }
+
+    #[test]
+    fn linked_instances_swap_bytes() {
+        let mut a = gameboy_running(&transfer_program(0x42, 0x81));
+        let mut b = gameboy_running(&transfer_program(0x99, 0x80));
+        run_linked(&mut a, &mut b, 2).unwrap();
+
+        assert_eq!(a.memory.read_hardware_register(HardwareRegister::SB), 0x99);
+        assert_eq!(b.memory.read_hardware_register(HardwareRegister::SB), 0x42);
+        assert_eq!(a.memory.read_hardware_register(HardwareRegister::SC) & 0x80, 0);
+        assert_eq!(b.memory.read_hardware_register(HardwareRegister::SC) & 0x80, 0);
+    }
+
+    #[test]
+    fn stopped_partner_keeps_the_link_running() {
+        let mut a = gameboy_running(&transfer_program(0x42, 0x81));
+        // SB = 0x5A, then STOP with no key held
+        let mut b = gameboy_running(&[0x3E, 0x5A, 0xE0, 0x01, 0x10, 0x00, 0x18, 0xFE]);
+        run_linked(&mut a, &mut b, 2).unwrap();
+
+        assert!(b.cpu.is_stopped);
+        assert_eq!(a.memory.read_hardware_register(HardwareRegister::SB), 0x5A);
+    }
+
+    #[test]
+    fn clocked_byte_crosses_the_cable() {
+        let (mut host, mut guest) = LinkCable::pair().unwrap();
+        assert_eq!(host.exchange(0x12), None);
+        let guest_thread = std::thread::spawn(move || {
+            let incoming = guest.step(DEFAULT_QUANTUM as u16, 0x34, true);
+            (incoming, guest)
+        });
+        assert_eq!(host.step(DEFAULT_QUANTUM as u16, 0x12, false), None);
+        let (incoming, guest) = guest_thread.join().unwrap();
+        assert_eq!(incoming, Some(0x12));
+        assert_eq!(host.exchange(0x12), Some(0x34));
+        assert_eq!(host.quantum, guest.quantum);
+    }
+
+    #[test]
+    fn silent_peer_times_out() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let guest = std::thread::spawn(move || LinkCable::connect_tcp(addr));
+        let (stream, _) = listener.accept().unwrap();
+        stream.set_read_timeout(Some(Duration::from_millis(50))).unwrap();
+        let mut host = LinkCable::handshake(stream, LinkRole::Host, DEFAULT_QUANTUM).unwrap();
+        let _guest = guest.join().unwrap().unwrap();
+
+        // The guest never steps, so the host's sync runs into the timeout
+        assert_eq!(host.exchange(0x12), None);
+        host.step(DEFAULT_QUANTUM as u16, 0x12, false);
+        assert!(host.stream.is_none());
+        assert_eq!(host.exchange(0x12), Some(0xFF));
+    }
+}
@@ -1,20 +1,57 @@
 mod cpu;
+mod cartridge;
+mod mbc;
 mod memory;
+mod boot_rom;
 mod data;
 mod interrupts;
 mod timer;
 mod gameboy_doctor;
+mod save;
+mod model;
+mod ppu;
+mod apu;
+mod joypad;
+mod serial;
+mod link;
+mod png;
+mod printer;
+mod wav;
+mod gbs;
+#[cfg(test)]
+mod test_roms;
 
 use cpu::CPU;
 use memory::Memory;
 use data::HardwareRegister;
-use interrupts::handle_interrupt;
+use interrupts::{Interrupt, handle_interrupt, request_interrupt};
 use timer::Timer;
+use mbc::RumbleEvent;
+use cartridge::{Cartridge, CartridgeError};
+use save::SaveFile;
+use boot_rom::BootRom;
+use model::Model;
+use joypad::Buttons;
+use serial::{Serial, SerialDevice, SerialLogger};
+use link::LinkCable;
+use printer::Printer;
+use wav::WavWriter;
+use gbs::Gbs;
+use apu::mixer::CLOCK_RATE;
+use ppu::{Ppu, Renderer, SCREEN_WIDTH, SCREEN_HEIGHT};
+use std::io;
+use std::path::Path;
+
+// 154 lines of 456 dots
+const CYCLES_PER_FRAME: u32 = 70224;
 
 pub struct GameBoy {
     pub cpu: CPU,
     pub memory: Memory,
     pub timer: Timer,
+    pub ppu: Ppu,
+    pub serial: Serial,
+    save: Option<SaveFile>,
 }
 
 impl GameBoy {
@@ -26,15 +63,88 @@ impl GameBoy {
             cpu: CPU::new(),
             memory: memory,
             timer: timer,
+            ppu: Ppu::new(),
+            serial: Serial::new(),
+            save: None,
+        }
+    }
+
+    pub fn load_rom<P: AsRef<Path>>(&mut self, path: P) -> Result<(), CartridgeError> {
+        let mut cartridge = Cartridge::load(&path)?;
+        println!("Loaded {}", cartridge.header);
+
+        if cartridge.header.cartridge_type.battery {
+            let mut save = SaveFile::for_rom(&path);
+            if let Some(data) = save.load()? {
+                println!("Loaded save {}", save.path().display());
+                cartridge.load_save_data(&data);
+            }
+            self.save = Some(save);
+        }
+
+        self.memory.load_cartridge(cartridge);
+        Ok(())
+    }
+
+    // Maps the boot ROM over the cartridge and starts executing it from 0x0000
+    // with cleared registers, the boot code sets up the rest
+    pub fn load_boot_rom<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        let boot_rom = BootRom::load(path)?;
+        self.memory.load_boot_rom(boot_rom);
+        self.cpu = CPU::new();
+        self.cpu.sp = 0x0000;
+        self.cpu.pc = 0x0000;
+        Ok(())
+    }
+
+    // Puts the CPU and I/O registers in the state the boot ROM leaves them in,
+    // for running without a boot ROM
+    pub fn set_model(&mut self, model: Model) {
+        self.memory.set_model(model);
+    }
+
+    pub fn apply_post_boot_state(&mut self, model: Model) {
+        self.set_model(model);
+        let header_checksum = self.memory.cartridge().map_or(0, |cartridge| cartridge.header.header_checksum);
+        model.apply_post_boot_cpu_state(&mut self.cpu, header_checksum);
+        model.apply_post_boot_io_state(&mut self.memory, &mut self.timer);
+    }
+
+    // Writes battery backed RAM to the .sav file if it changed
+    pub fn flush_save(&mut self) -> io::Result<()> {
+        match (&mut self.save, self.memory.cartridge()) {
+            (Some(save), Some(cartridge)) => save.flush(&cartridge.save_data(), cartridge.save_timestamp_len()),
+            _ => Ok(()),
         }
     }
 
     fn tick(&mut self, cycles: u16) {
         self.timer.step(cycles, &mut self.memory);
-        // ppu etc
+        self.memory.step_dma(cycles);
+        self.memory.step_apu(cycles);
+        self.ppu.step(cycles, &mut self.memory);
+        self.serial.step(cycles, &mut self.memory);
+        if let Some(cartridge) = self.memory.cartridge_mut() {
+            cartridge.step(cycles);
+        }
+        if self.save.as_mut().is_some_and(|save| save.step(cycles))
+            && let Err(err) = self.flush_save()
+        {
+            eprintln!("Autosave failed: {}", err);
+        }
     }
 
     fn step(&mut self) -> u16 {
+        // STOP halts the system clock until a selected key is held. The serial
+        // port keeps stepping so a link partner isn't left waiting on us.
+        if self.cpu.is_stopped {
+            if !self.memory.joypad().any_line_low() {
+                self.serial.step(4, &mut self.memory);
+                return 4;
+            }
+            self.cpu.is_stopped = false;
+        }
+
         // 2. HALT: if halted, either wake on interrupt (HALT bug) or burn cycles
         if self.cpu.is_halted {
             // print!("Halted");
@@ -84,19 +194,369 @@ impl GameBoy {
         self.tick(cycles);
         cycles
     }
-    
+
+    // Runs until the PPU finishes a frame, or for one frame's worth of cycles while the LCD is off
+    pub fn run_frame(&mut self) {
+        let frame = self.ppu.frame_count();
+        let mut cycles: u32 = 0;
+        while self.ppu.frame_count() == frame && cycles < CYCLES_PER_FRAME {
+            cycles += self.step() as u32;
+        }
+    }
+
+    // Runs at least `cycles` cycles, returns how many ran
+    pub fn run_cycles(&mut self, cycles: u64) -> u64 {
+        let mut ran: u64 = 0;
+        while ran < cycles {
+            ran += self.step() as u64;
+        }
+        ran
+    }
+
+    // Last completed frame, 160x144 shades from 0 (white) to 3 (black)
+    pub fn framebuffer(&self) -> &[u8; SCREEN_WIDTH * SCREEN_HEIGHT] {
+        self.ppu.framebuffer()
+    }
+
+    // Plugs a link partner, printer or logger into the serial port
+    pub fn attach_serial_device(&mut self, device: Box<dyn SerialDevice>) {
+        self.serial.attach(device);
+    }
+
+    // Replaces the set of held keys, raising the joypad interrupt on a new press
+    pub fn set_buttons(&mut self, buttons: Buttons) {
+        if self.memory.set_buttons(buttons) {
+            request_interrupt(&mut self.memory, Interrupt::Joypad);
+        }
+    }
+
+    // Digital output (0-15) of each sound channel at this moment
+    pub fn channel_outputs(&self) -> [u8; 4] {
+        self.memory.apu().channel_outputs()
+    }
+
+    // Starts collecting mixed audio at `sample_rate` Hz, e.g. 44100 or 48000.
+    // None stops it, samples aren't buffered until this is called.
+    pub fn set_audio_sample_rate(&mut self, sample_rate: Option<u32>) {
+        self.memory.apu_mut().set_sample_rate(sample_rate);
+    }
+
+    // Interleaved stereo samples (left, right) produced since the last drain
+    pub fn drain_audio_f32(&mut self) -> Vec<f32> {
+        self.memory.apu_mut().take_samples()
+    }
+
+    pub fn drain_audio_i16(&mut self) -> Vec<i16> {
+        self.drain_audio_f32().into_iter().map(wav::sample_to_i16).collect()
+    }
+
+    // Also collect each channel's output as its own mono stream, needs a sample rate
+    pub fn set_audio_channel_capture(&mut self, enabled: bool) {
+        self.memory.apu_mut().set_channel_capture(enabled);
+    }
+
+    // Mono samples of channels 1-4 produced since the last drain
+    pub fn drain_channel_audio(&mut self) -> [Vec<f32>; 4] {
+        self.memory.apu_mut().take_channel_samples()
+    }
+
+    pub fn rumble_active(&self) -> bool {
+        self.memory.cartridge().is_some_and(|cartridge| cartridge.rumble_active())
+    }
+
+    // Motor on/off changes since the last call, for hosts driving a rumble device
+    pub fn take_rumble_events(&mut self) -> Vec<RumbleEvent> {
+        match self.memory.cartridge_mut() {
+            Some(cartridge) => cartridge.take_rumble_events(),
+            None => Vec::new(),
+        }
+    }
+}
+
+impl Drop for GameBoy {
+    fn drop(&mut self) {
+        if let Err(err) = self.flush_save() {
+            eprintln!("Failed to write save: {}", err);
+        }
+    }
+}
+
+// `unix:<path>` for a Unix domain socket, anything else is a TCP address
+fn open_link(addr: &str, listen: bool) -> io::Result<LinkCable> {
+    #[cfg(unix)]
+    if let Some(path) = addr.strip_prefix("unix:") {
+        return if listen { LinkCable::listen_unix(path) } else { LinkCable::connect_unix(path) };
+    }
+    if listen { LinkCable::listen_tcp(addr) } else { LinkCable::connect_tcp(addr) }
+}
+
+// How long a headless run lasts
+enum RunLength {
+    Frames(u32),
+    Seconds(f64),
+}
+
+// Runs without a frontend and writes the mixed audio to `path`, plus one file
+// per channel next to it (`<name>_ch1.wav` ...) with `per_channel`
+fn export_wav(gameboy: &mut GameBoy, path: &Path, sample_rate: u32, per_channel: bool, length: RunLength) -> io::Result<()> {
+    gameboy.set_audio_sample_rate(Some(sample_rate));
+    gameboy.set_audio_channel_capture(per_channel);
+
+    let mut mixed = WavWriter::create(path, 2, sample_rate)?;
+    let mut channels = Vec::new();
+    if per_channel {
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        for channel in 1..=4 {
+            let channel_path = path.with_file_name(format!("{}_ch{}.wav", stem, channel));
+            channels.push(WavWriter::create(channel_path, 1, sample_rate)?);
+        }
+    }
+
+    let (frames, mut cycles_left) = match length {
+        RunLength::Frames(frames) => (frames, 0),
+        RunLength::Seconds(seconds) => (0, (seconds * CLOCK_RATE as f64) as u64),
+    };
+    let mut frame = 0;
+    while frame < frames || cycles_left > 0 {
+        if frame < frames {
+            gameboy.run_frame();
+            frame += 1;
+        } else {
+            let ran = gameboy.run_cycles(cycles_left.min(CYCLES_PER_FRAME as u64));
+            cycles_left = cycles_left.saturating_sub(ran);
+        }
+        mixed.write_samples(&gameboy.drain_audio_f32())?;
+        for (writer, samples) in channels.iter_mut().zip(gameboy.drain_channel_audio()) {
+            writer.write_samples(&samples)?;
+        }
+    }
+
+    mixed.finish()?;
+    for writer in channels {
+        writer.finish()?;
+    }
+    Ok(())
+}
+
+// `rustboy gbs <file.gbs> [--track N] [--seconds S] [--wav out.wav] [--wav-channels] [--sample-rate HZ] [--model NAME]`
+// Plays one track of a GBS file headless and writes it to a WAV file
+fn gbs_main(mut args: impl Iterator<Item = String>) {
+    let mut path = None;
+    let mut track = None;
+    let mut seconds = 60.0;
+    let mut wav_path = None;
+    let mut wav_channels = false;
+    let mut sample_rate: u32 = 44100;
+    let mut model = Model::Dmg;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--track" => {
+                let value = args.next().unwrap_or_default();
+                track = Some(value.parse::<u8>().unwrap_or_else(|_| {
+                    eprintln!("Invalid track '{}'", value);
+                    std::process::exit(1);
+                }));
+            }
+            "--seconds" => {
+                let value = args.next().unwrap_or_default();
+                seconds = value.parse::<f64>().ok().filter(|s| *s > 0.0).unwrap_or_else(|| {
+                    eprintln!("Invalid duration '{}'", value);
+                    std::process::exit(1);
+                });
+            }
+            "--wav" => wav_path = args.next(),
+            "--wav-channels" => wav_channels = true,
+            "--sample-rate" => {
+                let value = args.next().unwrap_or_default();
+                sample_rate = value.parse().ok().filter(|rate| *rate > 0).unwrap_or_else(|| {
+                    eprintln!("Invalid sample rate '{}'", value);
+                    std::process::exit(1);
+                });
+            }
+            "--model" => {
+                let name = args.next().unwrap_or_default();
+                model = Model::from_name(&name).unwrap_or_else(|| {
+                    eprintln!("Unknown model '{}', expected dmg0, dmg, mgb, sgb, sgb2, cgb or agb", name);
+                    std::process::exit(1);
+                });
+            }
+            _ => path = Some(arg),
+        }
+    }
+
+    let Some(path) = path else {
+        eprintln!("Usage: rustboy gbs <file.gbs> [--track N] [--seconds S] [--wav out.wav] [--wav-channels]");
+        std::process::exit(1);
+    };
+    let gbs = Gbs::load(&path).unwrap_or_else(|err| {
+        eprintln!("{}: {}", path, err);
+        std::process::exit(1);
+    });
+    println!("Loaded {}", gbs.header);
+
+    let track = track.unwrap_or(gbs.header.first_song);
+    let mut gameboy = GameBoy::new();
+    if let Err(err) = gbs.start(&mut gameboy, track, model) {
+        eprintln!("{}: {}", path, err);
+        std::process::exit(1);
+    }
+
+    let wav_path = wav_path.unwrap_or_else(|| {
+        let stem = Path::new(&path).file_stem().unwrap_or_default().to_string_lossy().into_owned();
+        Path::new(&path).with_file_name(format!("{}_{:02}.wav", stem, track)).to_string_lossy().into_owned()
+    });
+    println!("Playing track {} of {} for {} seconds into {}", track, gbs.header.song_count, seconds, wav_path);
+    if let Err(err) = export_wav(&mut gameboy, Path::new(&wav_path), sample_rate, wav_channels, RunLength::Seconds(seconds)) {
+        eprintln!("{}: {}", wav_path, err);
+        std::process::exit(1);
+    }
 }
 
 fn main() {
+    if std::env::args().nth(1).as_deref() == Some("gbs") {
+        gbs_main(std::env::args().skip(2));
+        return;
+    }
+
     let mut gameboy = GameBoy::new();
 
-    let rom_path = "/Users/jack/Code/rustboy/roms/gb-test-roms/cpu_instrs/individual/02-interrupts.gb";
+    let mut rom_path = None;
+    let mut boot_rom_path = None;
+    let mut model = Model::Dmg;
+    let mut renderer = Renderer::Scanline;
+    let mut link_listen = None;
+    let mut link_connect = None;
+    let mut link_local = None;
+    let mut printer_dir = None;
+    let mut frames: u32 = 600;
+    let mut seconds = None;
+    let mut wav_path = None;
+    let mut wav_channels = false;
+    let mut sample_rate: u32 = 44100;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--boot-rom" => boot_rom_path = args.next(),
+            "--model" => {
+                let name = args.next().unwrap_or_default();
+                model = Model::from_name(&name).unwrap_or_else(|| {
+                    eprintln!("Unknown model '{}', expected dmg0, dmg, mgb, sgb, sgb2, cgb or agb", name);
+                    std::process::exit(1);
+                });
+            }
+            "--renderer" => {
+                let name = args.next().unwrap_or_default();
+                renderer = Renderer::from_name(&name).unwrap_or_else(|| {
+                    eprintln!("Unknown renderer '{}', expected scanline or fifo", name);
+                    std::process::exit(1);
+                });
+            }
+            "--link-listen" => link_listen = args.next(),
+            "--link-connect" => link_connect = args.next(),
+            "--link-local" => link_local = args.next(),
+            "--printer" => printer_dir = args.next(),
+            "--frames" => {
+                let value = args.next().unwrap_or_default();
+                frames = value.parse().unwrap_or_else(|_| {
+                    eprintln!("Invalid frame count '{}'", value);
+                    std::process::exit(1);
+                });
+            }
+            "--seconds" => {
+                let value = args.next().unwrap_or_default();
+                seconds = Some(value.parse::<f64>().ok().filter(|s| *s > 0.0).unwrap_or_else(|| {
+                    eprintln!("Invalid duration '{}'", value);
+                    std::process::exit(1);
+                }));
+            }
+            "--wav" => wav_path = args.next(),
+            "--wav-channels" => wav_channels = true,
+            "--sample-rate" => {
+                let value = args.next().unwrap_or_default();
+                sample_rate = value.parse().ok().filter(|rate| *rate > 0).unwrap_or_else(|| {
+                    eprintln!("Invalid sample rate '{}'", value);
+                    std::process::exit(1);
+                });
+            }
+            _ => rom_path = Some(arg),
+        }
+    }
+
+    let Some(rom_path) = rom_path else {
+        eprintln!("Usage: rustboy <rom.gb> [--boot-rom file] [--model name] [--renderer scanline|fifo] [--frames N]");
+        eprintln!("               [--link-listen addr | --link-connect addr] [--link-local other.gb] [--printer dir]");
+        eprintln!("               [--seconds S] [--wav out.wav] [--wav-channels] [--sample-rate Hz]");
+        eprintln!("       rustboy gbs <file.gbs> [--track N] [--seconds S] [--wav out.wav] [--wav-channels] [--sample-rate Hz]");
+        std::process::exit(1);
+    };
+
+    gameboy.ppu.set_renderer(renderer);
+    let link = match (link_listen, link_connect) {
+        (Some(addr), _) => Some(open_link(&addr, true)),
+        (None, Some(addr)) => Some(open_link(&addr, false)),
+        (None, None) => None,
+    };
+    match link {
+        Some(Ok(cable)) => gameboy.attach_serial_device(Box::new(cable)),
+        Some(Err(err)) => {
+            eprintln!("Link cable: {}", err);
+            std::process::exit(1);
+        }
+        None => match printer_dir {
+            Some(dir) => gameboy.attach_serial_device(Box::new(Printer::new(dir))),
+            None => gameboy.attach_serial_device(Box::new(SerialLogger)),
+        },
+    }
+
+    if let Err(err) = gameboy.load_rom(&rom_path) {
+        eprintln!("{}: {}", rom_path, err);
+        std::process::exit(1);
+    }
 
-    gameboy_doctor::gb_doc_load_test_rom(&mut gameboy.memory, rom_path);
-    gameboy_doctor::gb_doc_set_inital_registers(&mut gameboy.cpu);
+    match boot_rom_path {
+        Some(path) => {
+            gameboy.set_model(model);
+            if let Err(err) = gameboy.load_boot_rom(&path) {
+                eprintln!("{}: {}", path, err);
+                std::process::exit(1);
+            }
+        }
+        None => gameboy.apply_post_boot_state(model),
+    }
     println!("Initial Registers");
     gameboy_doctor::gb_doc_print(&mut gameboy.cpu, &mut gameboy.memory);
 
+    // Two instances in one process, linked for `frames` frames
+    if let Some(other_rom) = link_local {
+        let mut other = GameBoy::new();
+        other.ppu.set_renderer(renderer);
+        if let Err(err) = other.load_rom(&other_rom) {
+            eprintln!("{}: {}", other_rom, err);
+            std::process::exit(1);
+        }
+        other.apply_post_boot_state(model);
+        if let Err(err) = link::run_linked(&mut gameboy, &mut other, frames) {
+            eprintln!("Link cable: {}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    // Headless audio capture for `frames` frames or `seconds` seconds
+    if let Some(path) = wav_path {
+        let length = match seconds {
+            Some(seconds) => RunLength::Seconds(seconds),
+            None => RunLength::Frames(frames),
+        };
+        if let Err(err) = export_wav(&mut gameboy, Path::new(&path), sample_rate, wav_channels, length) {
+            eprintln!("{}: {}", path, err);
+            std::process::exit(1);
+        }
+        return;
+    }
+
     let mut last_pc = 0;
     let mut stable_count = 0; 
 
@@ -104,7 +564,6 @@ fn main() {
     loop {
         gameboy.step();
         // You can add any logging/printing here if desired, e.g. println!("Cycles: {}", cycles);
-        gameboy_doctor::gb_doc_handle_serial(&mut gameboy.memory);
         // println!("{}", gameboy.cpu);
 
         // Test for infinite loop
@@ -0,0 +1,172 @@
+use super::{MemoryBankController, load_ram_image, read_rom_bank, ram_bank_offset};
+
+// 0000-1FFF	RAM enable (0x0A in the low nibble enables)
+// 2000-3FFF	ROM bank number, lower 5 bits
+// 4000-5FFF	RAM bank number or upper 2 bits of the ROM bank number
+// 6000-7FFF	Banking mode select
+pub struct Mbc1 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    ram_enabled: bool,
+    rom_bank: u8,   // 5 bit
+    upper_bank: u8, // 2 bit
+    advanced_mode: bool,
+}
+
+impl Mbc1 {
+    pub fn new(rom: Vec<u8>, ram_size: usize) -> Self {
+        Mbc1 {
+            rom,
+            ram: vec![0; ram_size],
+            ram_enabled: false,
+            rom_bank: 1,
+            upper_bank: 0,
+            advanced_mode: false,
+        }
+    }
+
+    fn ram_offset(&self, address: u16) -> Option<usize> {
+        if !self.ram_enabled {
+            return None;
+        }
+        let bank = if self.advanced_mode { self.upper_bank as usize } else { 0 };
+        ram_bank_offset(&self.ram, bank, address)
+    }
+}
+
+impl MemoryBankController for Mbc1 {
+    fn read_rom(&self, address: u16) -> u8 {
+        match address {
+            0x0000..=0x3FFF => {
+                // Mode 1 also applies the upper bits to the fixed bank (0x00/0x20/0x40/0x60)
+                let bank = if self.advanced_mode { (self.upper_bank as usize) << 5 } else { 0 };
+                read_rom_bank(&self.rom, bank, address)
+            }
+            _ => {
+                let bank = ((self.upper_bank as usize) << 5) | self.rom_bank as usize;
+                read_rom_bank(&self.rom, bank, address)
+            }
+        }
+    }
+
+    fn write_rom(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x1FFF => self.ram_enabled = value & 0x0F == 0x0A,
+            0x2000..=0x3FFF => {
+                // Only the 5 bit register is checked for zero, so 0x20/0x40/0x60 map to 0x21/0x41/0x61
+                self.rom_bank = value & 0x1F;
+                if self.rom_bank == 0 {
+                    self.rom_bank = 1;
+                }
+            }
+            0x4000..=0x5FFF => self.upper_bank = value & 0x03,
+            _ => self.advanced_mode = value & 0x01 != 0,
+        }
+    }
+
+    fn read_ram(&self, address: u16) -> u8 {
+        match self.ram_offset(address) {
+            Some(offset) => self.ram[offset],
+            None => 0xFF,
+        }
+    }
+
+    fn write_ram(&mut self, address: u16, value: u8) {
+        if let Some(offset) = self.ram_offset(address) {
+            self.ram[offset] = value;
+        }
+    }
+
+    fn save_data(&self) -> Vec<u8> {
+        self.ram.clone()
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        load_ram_image(&mut self.ram, data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::{RAM_BANK_SIZE, ROM_BANK_SIZE};
+
+    // 2 MiB ROM where every bank starts with its own number
+    fn mbc1(ram_size: usize) -> Mbc1 {
+        let mut rom = vec![0; 128 * ROM_BANK_SIZE];
+        for bank in 0..128 {
+            rom[bank * ROM_BANK_SIZE] = bank as u8;
+        }
+        Mbc1::new(rom, ram_size)
+    }
+
+    #[test]
+    fn bank_zero_selects_bank_one() {
+        let mut mbc = mbc1(0);
+        assert_eq!(mbc.read_rom(0x4000), 1);
+        mbc.write_rom(0x2000, 0x00);
+        assert_eq!(mbc.read_rom(0x4000), 1);
+        mbc.write_rom(0x3FFF, 0x1F);
+        assert_eq!(mbc.read_rom(0x4000), 0x1F);
+    }
+
+    #[test]
+    fn banks_20_40_60_alias_to_the_next_bank() {
+        let mut mbc = mbc1(0);
+        for upper in 1..4u8 {
+            mbc.write_rom(0x4000, upper);
+            mbc.write_rom(0x2000, 0x00);
+            assert_eq!(mbc.read_rom(0x4000), (upper << 5) | 1);
+            // Only the low 5 bits land in the lower register
+            mbc.write_rom(0x2000, 0xE0 | 0x02);
+            assert_eq!(mbc.read_rom(0x4000), (upper << 5) | 2);
+        }
+    }
+
+    #[test]
+    fn mode_1_banks_the_fixed_area() {
+        let mut mbc = mbc1(0);
+        mbc.write_rom(0x4000, 2);
+        assert_eq!(mbc.read_rom(0x0000), 0x00);
+        mbc.write_rom(0x6000, 1);
+        assert_eq!(mbc.read_rom(0x0000), 0x40);
+        assert_eq!(mbc.read_rom(0x4000), 0x41);
+        mbc.write_rom(0x6000, 0);
+        assert_eq!(mbc.read_rom(0x0000), 0x00);
+    }
+
+    #[test]
+    fn rom_banks_wrap_on_small_roms() {
+        let mut rom = vec![0; 4 * ROM_BANK_SIZE];
+        for bank in 0..4 {
+            rom[bank * ROM_BANK_SIZE] = bank as u8;
+        }
+        let mut mbc = Mbc1::new(rom, 0);
+        mbc.write_rom(0x2000, 0x06);
+        assert_eq!(mbc.read_rom(0x4000), 2);
+    }
+
+    #[test]
+    fn ram_enable_and_banking() {
+        let mut mbc = mbc1(4 * RAM_BANK_SIZE);
+        mbc.write_ram(0xA000, 0x12);
+        assert_eq!(mbc.read_ram(0xA000), 0xFF);
+
+        mbc.write_rom(0x0000, 0x0A);
+        mbc.write_ram(0xA000, 0x12);
+        assert_eq!(mbc.read_ram(0xA000), 0x12);
+
+        // The RAM bank only applies in mode 1
+        mbc.write_rom(0x4000, 2);
+        assert_eq!(mbc.read_ram(0xA000), 0x12);
+        mbc.write_rom(0x6000, 1);
+        assert_eq!(mbc.read_ram(0xA000), 0x00);
+        mbc.write_ram(0xA000, 0x34);
+        mbc.write_rom(0x6000, 0);
+        assert_eq!(mbc.read_ram(0xA000), 0x12);
+
+        mbc.write_rom(0x0000, 0x00);
+        assert_eq!(mbc.read_ram(0xA000), 0xFF);
+        assert_eq!(mbc.ram[2 * RAM_BANK_SIZE], 0x34);
+    }
+}
@@ -0,0 +1,150 @@
+use super::{MemoryBankController, read_rom_bank};
+
+// 0000-3FFF	Address bit 8 clear: RAM enable (0x0A in the low nibble enables)
+//          	Address bit 8 set: ROM bank number, 4 bits
+// A000-A1FF	512 x 4 bit built-in RAM, mirrored up to BFFF
+
+const RAM_SIZE: usize = 512;
+const PACKED_RAM_SIZE: usize = RAM_SIZE / 2;
+
+pub struct Mbc2 {
+    rom: Vec<u8>,
+    ram: [u8; RAM_SIZE],
+    ram_enabled: bool,
+    rom_bank: u8,
+}
+
+impl Mbc2 {
+    pub fn new(rom: Vec<u8>) -> Self {
+        Mbc2 {
+            rom,
+            ram: [0; RAM_SIZE],
+            ram_enabled: false,
+            rom_bank: 1,
+        }
+    }
+}
+
+impl MemoryBankController for Mbc2 {
+    fn read_rom(&self, address: u16) -> u8 {
+        match address {
+            0x0000..=0x3FFF => read_rom_bank(&self.rom, 0, address),
+            _ => read_rom_bank(&self.rom, self.rom_bank as usize, address),
+        }
+    }
+
+    fn write_rom(&mut self, address: u16, value: u8) {
+        if address > 0x3FFF {
+            return;
+        }
+        if address & 0x0100 == 0 {
+            self.ram_enabled = value & 0x0F == 0x0A;
+        } else {
+            self.rom_bank = value & 0x0F;
+            if self.rom_bank == 0 {
+                self.rom_bank = 1;
+            }
+        }
+    }
+
+    fn read_ram(&self, address: u16) -> u8 {
+        if !self.ram_enabled {
+            return 0xFF;
+        }
+        // Only the low nibble exists, the upper bits float high
+        self.ram[address as usize & (RAM_SIZE - 1)] | 0xF0
+    }
+
+    fn write_ram(&mut self, address: u16, value: u8) {
+        if self.ram_enabled {
+            self.ram[address as usize & (RAM_SIZE - 1)] = value & 0x0F;
+        }
+    }
+
+    // Saved as one nibble per byte, the layout most emulators use
+    fn save_data(&self) -> Vec<u8> {
+        self.ram.to_vec()
+    }
+
+    // Also accepts the 256 byte packed layout, two nibbles per byte with the low nibble first
+    fn load_save_data(&mut self, data: &[u8]) {
+        if data.len() == PACKED_RAM_SIZE {
+            for (i, byte) in data.iter().enumerate() {
+                self.ram[i * 2] = byte & 0x0F;
+                self.ram[i * 2 + 1] = byte >> 4;
+            }
+            return;
+        }
+        for (cell, byte) in self.ram.iter_mut().zip(data) {
+            *cell = byte & 0x0F;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::ROM_BANK_SIZE;
+
+    #[test]
+    fn address_bit_8_picks_the_register() {
+        let mut rom = vec![0; 16 * ROM_BANK_SIZE];
+        for bank in 0..16 {
+            rom[bank * ROM_BANK_SIZE] = bank as u8;
+        }
+        let mut mbc = Mbc2::new(rom);
+
+        // Bit 8 clear is RAM enable, even with a bank number written
+        mbc.write_rom(0x2000, 0x05);
+        assert_eq!(mbc.read_rom(0x4000), 1);
+        mbc.write_rom(0x2100, 0x05);
+        assert_eq!(mbc.read_rom(0x4000), 5);
+        mbc.write_rom(0x3FFF, 0xF0);
+        assert_eq!(mbc.read_rom(0x4000), 1);
+        // Outside 0000-3FFF nothing changes
+        mbc.write_rom(0x4100, 0x07);
+        assert_eq!(mbc.read_rom(0x4000), 1);
+
+        mbc.write_rom(0x0100, 0x0A);
+        assert_eq!(mbc.read_ram(0xA000), 0xFF);
+        mbc.write_rom(0x3EFF, 0x0A);
+        mbc.write_ram(0xA000, 0x00);
+        assert_eq!(mbc.read_ram(0xA000), 0xF0);
+    }
+
+    #[test]
+    fn ram_stores_nibbles_and_mirrors() {
+        let mut mbc = Mbc2::new(vec![0; 2 * ROM_BANK_SIZE]);
+        mbc.write_ram(0xA000, 0x0C);
+        assert_eq!(mbc.read_ram(0xA000), 0xFF);
+
+        mbc.write_rom(0x0000, 0x0A);
+        mbc.write_ram(0xA001, 0xAB);
+        assert_eq!(mbc.read_ram(0xA001), 0xFB);
+        assert_eq!(mbc.read_ram(0xA201), 0xFB);
+        assert_eq!(mbc.read_ram(0xBE01), 0xFB);
+        mbc.write_ram(0xBFFF, 0x07);
+        assert_eq!(mbc.read_ram(0xA1FF), 0xF7);
+    }
+
+    #[test]
+    fn save_layouts() {
+        let mut mbc = Mbc2::new(vec![0; 2 * ROM_BANK_SIZE]);
+        mbc.write_rom(0x0000, 0x0A);
+        mbc.write_ram(0xA000, 0x01);
+        mbc.write_ram(0xA001, 0x02);
+        let data = mbc.save_data();
+        assert_eq!(data.len(), RAM_SIZE);
+        assert_eq!(data[..2], [0x01, 0x02]);
+
+        let mut loaded = Mbc2::new(vec![0; 2 * ROM_BANK_SIZE]);
+        loaded.write_rom(0x0000, 0x0A);
+        loaded.load_save_data(&data);
+        assert_eq!(loaded.read_ram(0xA001), 0xF2);
+
+        let mut packed = vec![0; PACKED_RAM_SIZE];
+        packed[0] = 0x43;
+        loaded.load_save_data(&packed);
+        assert_eq!((loaded.read_ram(0xA000), loaded.read_ram(0xA001)), (0xF3, 0xF4));
+    }
+}
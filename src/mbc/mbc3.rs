@@ -0,0 +1,382 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::{MemoryBankController, load_ram_image, read_rom_bank, ram_bank_offset};
+
+// 0000-1FFF	RAM and RTC enable (0x0A in the low nibble enables)
+// 2000-3FFF	ROM bank number, 7 bits
+// 4000-5FFF	RAM bank number (00-07) or RTC register select (08-0C)
+// 6000-7FFF	Latch clock data (write 0x00 then 0x01)
+
+// The RTC runs off its own 32.768 kHz crystal, which is one tick per 128 CPU cycles
+const CYCLES_PER_SECOND: u32 = 4_194_304;
+
+// Footer used by BGB/VBA-M/SameBoy: 5 live registers, 5 latched registers
+// (each as a little endian u32) followed by a 64 bit UNIX timestamp
+pub const RTC_FOOTER_SIZE: usize = 48;
+const RTC_FOOTER_SIZE_32BIT: usize = 44;
+const RTC_TIMESTAMP_SIZE: usize = 8;
+
+const DH_DAY_HIGH: u8 = 0b0000_0001;
+const DH_HALT: u8 = 0b0100_0000;
+const DH_CARRY: u8 = 0b1000_0000;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RtcRegisters {
+    pub seconds: u8,
+    pub minutes: u8,
+    pub hours: u8,
+    pub day_low: u8,
+    pub day_high: u8, // bit 0 day bit 8, bit 6 halt, bit 7 day carry
+}
+
+impl RtcRegisters {
+    fn read(&self, select: u8) -> u8 {
+        match select {
+            0x08 => self.seconds,
+            0x09 => self.minutes,
+            0x0A => self.hours,
+            0x0B => self.day_low,
+            _ => self.day_high,
+        }
+    }
+
+    fn to_words(self) -> [u32; 5] {
+        [self.seconds, self.minutes, self.hours, self.day_low, self.day_high].map(|value| value as u32)
+    }
+
+    fn from_words(words: &[u32]) -> Self {
+        RtcRegisters {
+            seconds: (words[0] & 0x3F) as u8,
+            minutes: (words[1] & 0x3F) as u8,
+            hours: (words[2] & 0x1F) as u8,
+            day_low: words[3] as u8,
+            day_high: (words[4] as u8) & (DH_DAY_HIGH | DH_HALT | DH_CARRY),
+        }
+    }
+}
+
+pub struct Rtc {
+    pub live: RtcRegisters,
+    pub latched: RtcRegisters,
+    cycles: u32,
+    latch_armed: bool,
+}
+
+impl Rtc {
+    pub fn new() -> Self {
+        Rtc {
+            live: RtcRegisters::default(),
+            latched: RtcRegisters::default(),
+            cycles: 0,
+            latch_armed: false,
+        }
+    }
+
+    pub fn step(&mut self, cycles: u16) {
+        if self.live.day_high & DH_HALT != 0 {
+            return;
+        }
+        self.cycles += cycles as u32;
+        while self.cycles >= CYCLES_PER_SECOND {
+            self.cycles -= CYCLES_PER_SECOND;
+            self.tick_second();
+        }
+    }
+
+    // Counters are 6/6/5 bits wide: an out of range value keeps counting until it
+    // overflows its bit width and wraps to 0 without carrying into the next unit
+    fn tick_second(&mut self) {
+        let live = &mut self.live;
+        live.seconds = (live.seconds + 1) & 0x3F;
+        if live.seconds != 60 {
+            return;
+        }
+        live.seconds = 0;
+
+        live.minutes = (live.minutes + 1) & 0x3F;
+        if live.minutes != 60 {
+            return;
+        }
+        live.minutes = 0;
+
+        live.hours = (live.hours + 1) & 0x1F;
+        if live.hours != 24 {
+            return;
+        }
+        live.hours = 0;
+
+        let day = ((((live.day_high & DH_DAY_HIGH) as u16) << 8) | live.day_low as u16) + 1;
+        if day > 0x1FF {
+            live.day_high |= DH_CARRY;
+        }
+        live.day_low = day as u8;
+        live.day_high = (live.day_high & !DH_DAY_HIGH) | ((day >> 8) as u8 & DH_DAY_HIGH);
+    }
+
+    fn write_latch(&mut self, value: u8) {
+        if self.latch_armed && value == 0x01 {
+            self.latched = self.live;
+        }
+        self.latch_armed = value == 0x00;
+    }
+
+    fn write(&mut self, select: u8, value: u8) {
+        match select {
+            0x08 => {
+                self.live.seconds = value & 0x3F;
+                // Writing seconds resets the sub-second divider
+                self.cycles = 0;
+            }
+            0x09 => self.live.minutes = value & 0x3F,
+            0x0A => self.live.hours = value & 0x1F,
+            0x0B => self.live.day_low = value,
+            _ => self.live.day_high = value & (DH_DAY_HIGH | DH_HALT | DH_CARRY),
+        }
+    }
+
+    pub fn to_footer(&self) -> [u8; RTC_FOOTER_SIZE] {
+        let mut footer = [0u8; RTC_FOOTER_SIZE];
+        let words = self.live.to_words().into_iter().chain(self.latched.to_words());
+        for (i, word) in words.enumerate() {
+            footer[i * 4..i * 4 + 4].copy_from_slice(&word.to_le_bytes());
+        }
+        // The timestamp is only written for other emulators. The clock runs off
+        // emulated cycles, so load ignores it and runs stay deterministic.
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        footer[40..40 + RTC_TIMESTAMP_SIZE].copy_from_slice(&timestamp.to_le_bytes());
+        footer
+    }
+
+    pub fn load_footer(&mut self, footer: &[u8]) {
+        if footer.len() != RTC_FOOTER_SIZE && footer.len() != RTC_FOOTER_SIZE_32BIT {
+            return;
+        }
+        let words: Vec<u32> = footer[..40]
+            .chunks_exact(4)
+            .map(|chunk| u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+            .collect();
+        self.live = RtcRegisters::from_words(&words[0..5]);
+        self.latched = RtcRegisters::from_words(&words[5..10]);
+        self.cycles = 0;
+    }
+}
+
+pub struct Mbc3 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    rtc: Option<Rtc>,
+    ram_enabled: bool,
+    rom_bank: u8,
+    ram_select: u8,
+}
+
+impl Mbc3 {
+    pub fn new(rom: Vec<u8>, ram_size: usize, has_rtc: bool) -> Self {
+        Mbc3 {
+            rom,
+            ram: vec![0; ram_size],
+            rtc: if has_rtc { Some(Rtc::new()) } else { None },
+            ram_enabled: false,
+            rom_bank: 1,
+            ram_select: 0,
+        }
+    }
+
+    fn ram_offset(&self, address: u16) -> Option<usize> {
+        if !self.ram_enabled || self.ram_select > 0x07 {
+            return None;
+        }
+        ram_bank_offset(&self.ram, self.ram_select as usize, address)
+    }
+
+    fn rtc_selected(&self) -> bool {
+        self.ram_enabled && self.rtc.is_some() && (0x08..=0x0C).contains(&self.ram_select)
+    }
+}
+
+impl MemoryBankController for Mbc3 {
+    fn read_rom(&self, address: u16) -> u8 {
+        match address {
+            0x0000..=0x3FFF => read_rom_bank(&self.rom, 0, address),
+            _ => read_rom_bank(&self.rom, self.rom_bank as usize, address),
+        }
+    }
+
+    fn write_rom(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x1FFF => self.ram_enabled = value & 0x0F == 0x0A,
+            0x2000..=0x3FFF => {
+                self.rom_bank = value & 0x7F;
+                if self.rom_bank == 0 {
+                    self.rom_bank = 1;
+                }
+            }
+            0x4000..=0x5FFF => self.ram_select = value & 0x0F,
+            _ => {
+                if let Some(rtc) = &mut self.rtc {
+                    rtc.write_latch(value);
+                }
+            }
+        }
+    }
+
+    fn read_ram(&self, address: u16) -> u8 {
+        if self.rtc_selected() {
+            return self.rtc.as_ref().map_or(0xFF, |rtc| rtc.latched.read(self.ram_select));
+        }
+        match self.ram_offset(address) {
+            Some(offset) => self.ram[offset],
+            None => 0xFF,
+        }
+    }
+
+    fn write_ram(&mut self, address: u16, value: u8) {
+        if self.rtc_selected() {
+            if let Some(rtc) = &mut self.rtc {
+                rtc.write(self.ram_select, value);
+            }
+            return;
+        }
+        if let Some(offset) = self.ram_offset(address) {
+            self.ram[offset] = value;
+        }
+    }
+
+    fn step(&mut self, cycles: u16) {
+        if let Some(rtc) = &mut self.rtc {
+            rtc.step(cycles);
+        }
+    }
+
+    fn save_data(&self) -> Vec<u8> {
+        let mut data = self.ram.clone();
+        if let Some(rtc) = &self.rtc {
+            data.extend_from_slice(&rtc.to_footer());
+        }
+        data
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        load_ram_image(&mut self.ram, data);
+        let ram_len = self.ram.len().min(data.len());
+        if let Some(rtc) = &mut self.rtc {
+            rtc.load_footer(&data[ram_len..]);
+        }
+    }
+
+    fn save_timestamp_len(&self) -> usize {
+        if self.rtc.is_some() { RTC_TIMESTAMP_SIZE } else { 0 }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::RAM_BANK_SIZE;
+
+    fn mbc3() -> Mbc3 {
+        let mut mbc = Mbc3::new(vec![0; 0x8000], 4 * RAM_BANK_SIZE, true);
+        mbc.write_rom(0x0000, 0x0A);
+        mbc
+    }
+
+    fn read_rtc(mbc: &mut Mbc3, select: u8) -> u8 {
+        mbc.write_rom(0x4000, select);
+        mbc.read_ram(0xA000)
+    }
+
+    fn step_seconds(mbc: &mut Mbc3, seconds: u32) {
+        for _ in 0..seconds * CYCLES_PER_SECOND / 4096 {
+            mbc.step(4096);
+        }
+    }
+
+    fn latch(mbc: &mut Mbc3) {
+        mbc.write_rom(0x6000, 0x00);
+        mbc.write_rom(0x6000, 0x01);
+    }
+
+    #[test]
+    fn reads_see_the_latched_time() {
+        let mut mbc = mbc3();
+        step_seconds(&mut mbc, 3);
+        assert_eq!(read_rtc(&mut mbc, 0x08), 0);
+
+        latch(&mut mbc);
+        assert_eq!(read_rtc(&mut mbc, 0x08), 3);
+        step_seconds(&mut mbc, 2);
+        assert_eq!(read_rtc(&mut mbc, 0x08), 3);
+        latch(&mut mbc);
+        assert_eq!(read_rtc(&mut mbc, 0x08), 5);
+    }
+
+    #[test]
+    fn latch_needs_0_then_1() {
+        let mut mbc = mbc3();
+        step_seconds(&mut mbc, 1);
+        mbc.write_rom(0x6000, 0x01);
+        assert_eq!(read_rtc(&mut mbc, 0x08), 0);
+        mbc.write_rom(0x6000, 0x00);
+        mbc.write_rom(0x6000, 0x02);
+        mbc.write_rom(0x6000, 0x01);
+        assert_eq!(read_rtc(&mut mbc, 0x08), 0);
+        latch(&mut mbc);
+        assert_eq!(read_rtc(&mut mbc, 0x08), 1);
+    }
+
+    #[test]
+    fn rolls_over_into_the_day_carry() {
+        let mut mbc = mbc3();
+        for (select, value) in [(0x08, 59), (0x09, 59), (0x0A, 23), (0x0B, 0xFF), (0x0C, DH_DAY_HIGH)] {
+            mbc.write_rom(0x4000, select);
+            mbc.write_ram(0xA000, value);
+        }
+        step_seconds(&mut mbc, 1);
+        latch(&mut mbc);
+        let time: Vec<u8> = (0x08..=0x0C).map(|select| read_rtc(&mut mbc, select)).collect();
+        assert_eq!(time, [0, 0, 0, 0, DH_CARRY]);
+    }
+
+    #[test]
+    fn halt_stops_the_clock() {
+        let mut mbc = mbc3();
+        mbc.write_rom(0x4000, 0x0C);
+        mbc.write_ram(0xA000, DH_HALT);
+        step_seconds(&mut mbc, 5);
+        latch(&mut mbc);
+        assert_eq!(read_rtc(&mut mbc, 0x08), 0);
+    }
+
+    #[test]
+    fn ram_banks_and_rtc_share_the_window() {
+        let mut mbc = mbc3();
+        mbc.write_rom(0x4000, 0x03);
+        mbc.write_ram(0xA000, 0x42);
+        assert_eq!(read_rtc(&mut mbc, 0x08), 0);
+        assert_eq!(read_rtc(&mut mbc, 0x03), 0x42);
+        assert_eq!(read_rtc(&mut mbc, 0x0D), 0xFF);
+    }
+
+    #[test]
+    fn save_footer_round_trip() {
+        let mut mbc = mbc3();
+        step_seconds(&mut mbc, 61);
+        latch(&mut mbc);
+        step_seconds(&mut mbc, 1);
+        mbc.write_ram(0xA000, 0x99);
+        let data = mbc.save_data();
+        assert_eq!(data.len(), 4 * RAM_BANK_SIZE + RTC_FOOTER_SIZE);
+        assert_eq!(mbc.save_timestamp_len(), RTC_TIMESTAMP_SIZE);
+
+        let mut loaded = mbc3();
+        loaded.load_save_data(&data);
+        let rtc = loaded.rtc.as_ref().unwrap();
+        assert_eq!((rtc.live.minutes, rtc.live.seconds), (1, 2));
+        assert_eq!((rtc.latched.minutes, rtc.latched.seconds), (1, 1));
+
+        // Older saves without the top half of the timestamp load too
+        let mut loaded = mbc3();
+        loaded.load_save_data(&data[..data.len() - 4]);
+        assert_eq!(loaded.rtc.as_ref().unwrap().live.seconds, 2);
+    }
+}
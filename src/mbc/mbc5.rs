@@ -0,0 +1,221 @@
+use super::{MemoryBankController, load_ram_image, read_rom_bank, ram_bank_offset};
+
+// 0000-1FFF	RAM enable (0x0A enables)
+// 2000-2FFF	ROM bank number, lower 8 bits
+// 3000-3FFF	ROM bank number, bit 8
+// 4000-5FFF	RAM bank number (00-0F), bit 3 drives the motor on rumble carts
+
+const RUMBLE_MOTOR: u8 = 0b0000_1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RumbleEvent {
+    pub cycle: u64, // cycles since power on
+    pub on: bool,
+}
+
+pub struct Mbc5 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    ram_enabled: bool,
+    rom_bank: u16, // 9 bit, bank 0 is selectable
+    ram_bank: u8,
+    rumble: bool,
+    motor_on: bool,
+    cycles: u64,
+    rumble_events: Vec<RumbleEvent>,
+}
+
+impl Mbc5 {
+    pub fn new(rom: Vec<u8>, ram_size: usize, rumble: bool) -> Self {
+        Mbc5 {
+            rom,
+            ram: vec![0; ram_size],
+            ram_enabled: false,
+            rom_bank: 1,
+            ram_bank: 0,
+            rumble,
+            motor_on: false,
+            cycles: 0,
+            rumble_events: Vec::new(),
+        }
+    }
+
+    fn ram_offset(&self, address: u16) -> Option<usize> {
+        if !self.ram_enabled {
+            return None;
+        }
+        ram_bank_offset(&self.ram, self.ram_bank as usize, address)
+    }
+
+    fn write_ram_bank(&mut self, value: u8) {
+        if !self.rumble {
+            self.ram_bank = value & 0x0F;
+            return;
+        }
+
+        self.ram_bank = value & 0x07;
+        let motor_on = value & RUMBLE_MOTOR != 0;
+        if motor_on != self.motor_on {
+            self.motor_on = motor_on;
+            self.rumble_events.push(RumbleEvent { cycle: self.cycles, on: motor_on });
+        }
+    }
+}
+
+impl MemoryBankController for Mbc5 {
+    fn read_rom(&self, address: u16) -> u8 {
+        match address {
+            0x0000..=0x3FFF => read_rom_bank(&self.rom, 0, address),
+            _ => read_rom_bank(&self.rom, self.rom_bank as usize, address),
+        }
+    }
+
+    fn write_rom(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x1FFF => self.ram_enabled = value == 0x0A,
+            0x2000..=0x2FFF => self.rom_bank = (self.rom_bank & 0x100) | value as u16,
+            0x3000..=0x3FFF => self.rom_bank = (self.rom_bank & 0xFF) | (((value & 0x01) as u16) << 8),
+            0x4000..=0x5FFF => self.write_ram_bank(value),
+            _ => {}
+        }
+    }
+
+    fn read_ram(&self, address: u16) -> u8 {
+        match self.ram_offset(address) {
+            Some(offset) => self.ram[offset],
+            None => 0xFF,
+        }
+    }
+
+    fn write_ram(&mut self, address: u16, value: u8) {
+        if let Some(offset) = self.ram_offset(address) {
+            self.ram[offset] = value;
+        }
+    }
+
+    fn step(&mut self, cycles: u16) {
+        self.cycles += cycles as u64;
+    }
+
+    fn save_data(&self) -> Vec<u8> {
+        self.ram.clone()
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        load_ram_image(&mut self.ram, data);
+    }
+
+    fn rumble_active(&self) -> bool {
+        self.motor_on
+    }
+
+    fn take_rumble_events(&mut self) -> Vec<RumbleEvent> {
+        std::mem::take(&mut self.rumble_events)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::{RAM_BANK_SIZE, ROM_BANK_SIZE};
+
+    // 8 MiB ROM where every bank starts with its number, low byte then high byte
+    fn mbc5(ram_size: usize, rumble: bool) -> Mbc5 {
+        let mut rom = vec![0; 512 * ROM_BANK_SIZE];
+        for bank in 0..512 {
+            rom[bank * ROM_BANK_SIZE..bank * ROM_BANK_SIZE + 2].copy_from_slice(&(bank as u16).to_le_bytes());
+        }
+        Mbc5::new(rom, ram_size, rumble)
+    }
+
+    fn switchable_bank(mbc: &Mbc5) -> u16 {
+        u16::from_le_bytes([mbc.read_rom(0x4000), mbc.read_rom(0x4001)])
+    }
+
+    #[test]
+    fn nine_bit_rom_bank() {
+        let mut mbc = mbc5(0, false);
+        assert_eq!(switchable_bank(&mbc), 1);
+        mbc.write_rom(0x2000, 0xFF);
+        assert_eq!(switchable_bank(&mbc), 0xFF);
+        mbc.write_rom(0x3000, 0x01);
+        assert_eq!(switchable_bank(&mbc), 0x1FF);
+        mbc.write_rom(0x2FFF, 0x23);
+        assert_eq!(switchable_bank(&mbc), 0x123);
+        // Only bit 0 of the upper register is used
+        mbc.write_rom(0x3FFF, 0xFE);
+        assert_eq!(switchable_bank(&mbc), 0x023);
+        assert_eq!(mbc.read_rom(0x0000), 0x00);
+    }
+
+    #[test]
+    fn bank_zero_maps_at_4000() {
+        let mut mbc = mbc5(0, false);
+        mbc.write_rom(0x2000, 0x00);
+        assert_eq!(switchable_bank(&mbc), 0);
+        mbc.write_rom(0x3000, 0x01);
+        assert_eq!(switchable_bank(&mbc), 0x100);
+    }
+
+    #[test]
+    fn sixteen_ram_banks() {
+        let mut mbc = mbc5(16 * RAM_BANK_SIZE, false);
+        mbc.write_ram(0xA000, 0x55);
+        assert_eq!(mbc.read_ram(0xA000), 0xFF);
+
+        // Only exactly 0x0A enables RAM
+        mbc.write_rom(0x0000, 0x1A);
+        mbc.write_ram(0xA000, 0x55);
+        assert_eq!(mbc.read_ram(0xA000), 0xFF);
+
+        mbc.write_rom(0x0000, 0x0A);
+        for bank in 0..16 {
+            mbc.write_rom(0x4000, bank);
+            mbc.write_ram(0xBFFF, bank + 1);
+        }
+        for bank in 0..16 {
+            mbc.write_rom(0x5FFF, bank);
+            assert_eq!(mbc.read_ram(0xBFFF), bank + 1);
+        }
+        // The bank number is 4 bits
+        mbc.write_rom(0x4000, 0x12);
+        assert_eq!(mbc.read_ram(0xBFFF), 3);
+        assert!(mbc.take_rumble_events().is_empty());
+    }
+
+    #[test]
+    fn rumble_motor_is_not_a_bank_bit() {
+        let mut mbc = mbc5(16 * RAM_BANK_SIZE, true);
+        mbc.write_rom(0x0000, 0x0A);
+        mbc.write_rom(0x4000, 0x01);
+        mbc.write_ram(0xA000, 0x11);
+        mbc.write_rom(0x4000, 0x09);
+        assert!(mbc.rumble_active());
+        assert_eq!(mbc.read_ram(0xA000), 0x11);
+        mbc.write_ram(0xA000, 0x22);
+        mbc.write_rom(0x4000, 0x01);
+        assert!(!mbc.rumble_active());
+        assert_eq!(mbc.read_ram(0xA000), 0x22);
+    }
+
+    #[test]
+    fn rumble_events_carry_the_cycle() {
+        let mut mbc = mbc5(0, true);
+        mbc.step(100);
+        mbc.write_rom(0x4000, 0x08);
+        mbc.step(40);
+        // Staying on doesn't add an event
+        mbc.write_rom(0x4000, 0x0F);
+        mbc.step(60);
+        mbc.write_rom(0x4000, 0x00);
+        assert_eq!(
+            mbc.take_rumble_events(),
+            [RumbleEvent { cycle: 100, on: true }, RumbleEvent { cycle: 200, on: false }]
+        );
+        assert!(mbc.take_rumble_events().is_empty());
+
+        mbc.step(1);
+        mbc.write_rom(0x4000, 0x08);
+        assert_eq!(mbc.take_rumble_events(), [RumbleEvent { cycle: 201, on: true }]);
+    }
+}
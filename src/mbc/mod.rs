@@ -0,0 +1,67 @@
+pub mod rom_only;
+pub mod mbc1;
+pub mod mbc2;
+pub mod mbc3;
+pub mod mbc5;
+
+pub use rom_only::RomOnly;
+pub use mbc1::Mbc1;
+pub use mbc2::Mbc2;
+pub use mbc3::Mbc3;
+pub use mbc5::{Mbc5, RumbleEvent};
+
+use crate::cartridge::{ROM_BANK_SIZE, RAM_BANK_SIZE};
+
+// Memory bank controllers sit between the CPU and the cartridge ROM/RAM.
+// Writes to 0x0000-0x7FFF land in the controller's registers, reads from
+// 0x0000-0x7FFF and 0xA000-0xBFFF are routed to the selected banks.
+pub trait MemoryBankController: Send {
+    fn read_rom(&self, address: u16) -> u8;
+    fn write_rom(&mut self, address: u16, value: u8);
+    fn read_ram(&self, address: u16) -> u8;
+    fn write_ram(&mut self, address: u16, value: u8);
+
+    // Advances anything clocked on the cartridge, e.g. the MBC3 RTC
+    fn step(&mut self, _cycles: u16) {}
+
+    // Battery backed state: the RAM image, followed by any mapper specific footer
+    fn save_data(&self) -> Vec<u8>;
+    fn load_save_data(&mut self, data: &[u8]);
+
+    // Trailing bytes of the save that change without the state changing,
+    // e.g. the wall clock timestamp in the RTC footer
+    fn save_timestamp_len(&self) -> usize {
+        0
+    }
+
+    // Motor state on rumble carts, events are timestamped in cycles since power on
+    fn rumble_active(&self) -> bool {
+        false
+    }
+
+    fn take_rumble_events(&mut self) -> Vec<RumbleEvent> {
+        Vec::new()
+    }
+}
+
+// Copies a save image into RAM, ignoring anything past the end of the RAM
+pub fn load_ram_image(ram: &mut [u8], data: &[u8]) {
+    let len = ram.len().min(data.len());
+    ram[..len].copy_from_slice(&data[..len]);
+}
+
+// Reads from a 16 KiB ROM bank, wrapping bank numbers larger than the ROM
+pub fn read_rom_bank(rom: &[u8], bank: usize, address: u16) -> u8 {
+    let banks = (rom.len() / ROM_BANK_SIZE).max(1);
+    let offset = (bank % banks) * ROM_BANK_SIZE + (address as usize & (ROM_BANK_SIZE - 1));
+    rom.get(offset).copied().unwrap_or(0xFF)
+}
+
+// Offset into external RAM for an 8 KiB bank, wrapping for RAM smaller than the bank count
+pub fn ram_bank_offset(ram: &[u8], bank: usize, address: u16) -> Option<usize> {
+    if ram.is_empty() {
+        return None;
+    }
+    let offset = bank * RAM_BANK_SIZE + (address as usize & (RAM_BANK_SIZE - 1));
+    Some(offset % ram.len())
+}
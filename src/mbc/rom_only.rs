@@ -0,0 +1,42 @@
+use super::{MemoryBankController, load_ram_image, ram_bank_offset};
+
+// 32 KiB ROM with no banking, optionally with up to 8 KiB of RAM
+pub struct RomOnly {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+}
+
+impl RomOnly {
+    pub fn new(rom: Vec<u8>, ram_size: usize) -> Self {
+        RomOnly { rom, ram: vec![0; ram_size] }
+    }
+}
+
+impl MemoryBankController for RomOnly {
+    fn read_rom(&self, address: u16) -> u8 {
+        self.rom.get(address as usize).copied().unwrap_or(0xFF)
+    }
+
+    fn write_rom(&mut self, _address: u16, _value: u8) {}
+
+    fn read_ram(&self, address: u16) -> u8 {
+        match ram_bank_offset(&self.ram, 0, address) {
+            Some(offset) => self.ram[offset],
+            None => 0xFF,
+        }
+    }
+
+    fn write_ram(&mut self, address: u16, value: u8) {
+        if let Some(offset) = ram_bank_offset(&self.ram, 0, address) {
+            self.ram[offset] = value;
+        }
+    }
+
+    fn save_data(&self) -> Vec<u8> {
+        self.ram.clone()
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        load_ram_image(&mut self.ram, data);
+    }
+}
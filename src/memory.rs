@@ -1,4 +1,11 @@
 use crate::data::HardwareRegister;
+use crate::cartridge::Cartridge;
+use crate::mbc::MemoryBankController;
+use crate::boot_rom::BootRom;
+use crate::model::Model;
+use crate::joypad::{Joypad, Buttons};
+use crate::interrupts::Interrupt;
+use crate::apu::{Apu, APU_START, APU_END};
 
 // Start	    End	Description	Notes
 // 0000	3FFF	16 KiB ROM bank 00	From cartridge, usually a fixed bank
@@ -14,37 +21,557 @@ use crate::data::HardwareRegister;
 // FF80	FFFE	High RAM (HRAM)	
 // FFFF	FFFF	Interrupt Enable register (IE)	
 
+const OAM_START: u16 = 0xFE00;
+
+const LCDC_ENABLE: u8 = 0b1000_0000;
+const STAT_MODE: u8 = 0b0000_0011;
+const MODE_OAM_SCAN: u8 = 2;
+const MODE_DRAWING: u8 = 3;
+const OAM_DMA_LENGTH: u16 = 0xA0;
+
+// OAM DMA copies 160 bytes from XX00-XX9F into OAM, one byte per M-cycle,
+// after a one M-cycle startup delay
+struct OamDma {
+    source: u16,
+    index: u16,
+    delay: u8,
+    cycles: u16,
+    byte: u8, // last byte put on the bus
+}
+
+impl OamDma {
+    fn new(value: u8) -> Self {
+        OamDma { source: (value as u16) << 8, index: 0, delay: 1, cycles: 0, byte: 0xFF }
+    }
+
+    // VRAM sits on its own bus, everything else below OAM shares the external bus
+    fn conflicts_with(&self, address: u16) -> bool {
+        is_vram(self.source) == is_vram(address)
+    }
+}
+
+fn is_vram(address: u16) -> bool {
+    (0x8000..=0x9FFF).contains(&address)
+}
+
+// Bits of the I/O registers that don't exist and read back as 1.
+// Registers that aren't mapped at all read 0xFF.
+fn io_unused_bits(address: u16, cgb: bool) -> u8 {
+    match address {
+        0xFF00 => 0xC0,                      // P1
+        0xFF01 => 0x00,                      // SB
+        0xFF02 => if cgb { 0x7C } else { 0x7E }, // SC
+        0xFF04..=0xFF06 => 0x00,             // DIV, TIMA, TMA
+        0xFF07 => 0xF8,                      // TAC
+        0xFF0F => 0xE0,                      // IF
+        0xFF10 => 0x80,                      // NR10
+        0xFF11 | 0xFF16 => 0x3F,             // NR11, NR21 - length is write only
+        0xFF12 | 0xFF17 | 0xFF21 => 0x00,    // NR12, NR22, NR42
+        0xFF13 | 0xFF18 | 0xFF1D => 0xFF,    // NR13, NR23, NR33 - write only
+        0xFF14 | 0xFF19 | 0xFF1E | 0xFF23 => 0xBF, // NRx4 - only the length enable reads back
+        0xFF1A => 0x7F,                      // NR30
+        0xFF1B | 0xFF20 => 0xFF,             // NR31, NR41 - write only
+        0xFF1C => 0x9F,                      // NR32
+        0xFF22 | 0xFF24 | 0xFF25 => 0x00,    // NR43, NR50, NR51
+        0xFF26 => 0x70,                      // NR52
+        0xFF30..=0xFF3F => 0x00,             // Wave RAM
+        0xFF40 => 0x00,                      // LCDC
+        0xFF41 => 0x80,                      // STAT
+        0xFF42..=0xFF4B => 0x00,             // SCY, SCX, LY, LYC, DMA, BGP, OBP0, OBP1, WY, WX
+        0xFF4D if cgb => 0x7E,               // KEY1
+        0xFF4F if cgb => 0xFE,               // VBK
+        0xFF55 if cgb => 0x00,               // HDMA5
+        0xFF56 if cgb => 0x3C,               // RP
+        0xFF68 | 0xFF6A if cgb => 0x40,      // BCPS, OCPS
+        0xFF69 | 0xFF6B if cgb => 0x00,      // BCPD, OCPD
+        0xFF6C if cgb => 0xFE,               // OPRI
+        0xFF70 if cgb => 0xF8,               // SVBK
+        0xFF76 | 0xFF77 if cgb => 0x00,      // PCM12, PCM34
+        _ => 0xFF,
+    }
+}
+
 pub struct Memory {
     data: [u8; 0x10000],
+    cartridge: Option<Cartridge>,
+    mapper: Option<Box<dyn MemoryBankController>>, // ROM and RAM mapped without a cartridge, e.g. a GBS file
+    boot_rom: Option<BootRom>,
+    dma: Option<OamDma>,
+    model: Model,
+    joypad: Joypad,
+    apu: Apu,
 }
 
 impl Memory {
     pub fn new() -> Self {
-        Memory { data: [0; 0x10000] }
+        Memory { data: [0; 0x10000], cartridge: None, mapper: None, boot_rom: None, dma: None, model: Model::Dmg, joypad: Joypad::new(), apu: Apu::new() }
+    }
+
+    pub fn set_model(&mut self, model: Model) {
+        self.model = model;
+        self.apu.set_cgb(model.is_cgb());
+    }
+
+    pub fn model(&self) -> Model {
+        self.model
+    }
+
+    // Returns true if a key press pulled a selected input line low
+    pub fn set_buttons(&mut self, buttons: Buttons) -> bool {
+        self.joypad.set_buttons(buttons)
+    }
+
+    pub fn joypad(&self) -> &Joypad {
+        &self.joypad
+    }
+
+    pub fn apu(&self) -> &Apu {
+        &self.apu
+    }
+
+    pub fn apu_mut(&mut self) -> &mut Apu {
+        &mut self.apu
+    }
+
+    // The frame sequencer follows DIV, so this runs after the timer
+    pub fn step_apu(&mut self, cycles: u16) {
+        self.apu.step(cycles, self.data[HardwareRegister::DIV as usize]);
+    }
+
+    pub fn load_boot_rom(&mut self, boot_rom: BootRom) {
+        self.boot_rom = Some(boot_rom);
+    }
+
+    pub fn boot_rom_mapped(&self) -> bool {
+        self.boot_rom.is_some()
     }
 
+    pub fn load_cartridge(&mut self, cartridge: Cartridge) {
+        self.cartridge = Some(cartridge);
+    }
+
+    // Maps ROM and external RAM through `mapper` when no cartridge is loaded
+    pub fn load_mapper(&mut self, mapper: Box<dyn MemoryBankController>) {
+        self.mapper = Some(mapper);
+    }
+
+    pub fn cartridge(&self) -> Option<&Cartridge> {
+        self.cartridge.as_ref()
+    }
+
+    pub fn cartridge_mut(&mut self) -> Option<&mut Cartridge> {
+        self.cartridge.as_mut()
+    }
+
+    pub fn step_dma(&mut self, cycles: u16) {
+        let Some(mut dma) = self.dma.take() else {
+            return;
+        };
+
+        dma.cycles += cycles;
+        while dma.cycles >= 4 && dma.index < OAM_DMA_LENGTH {
+            dma.cycles -= 4;
+            if dma.delay > 0 {
+                dma.delay -= 1;
+                continue;
+            }
+            // Sources above DFFF read from the echo of work RAM
+            let mut source = dma.source + dma.index;
+            if source >= 0xE000 {
+                source -= 0x2000;
+            }
+            dma.byte = self.read_mapped(source);
+            self.data[(OAM_START + dma.index) as usize] = dma.byte;
+            dma.index += 1;
+        }
+
+        if dma.index < OAM_DMA_LENGTH {
+            self.dma = Some(dma);
+        }
+    }
+
+    // CPU view of memory. While OAM DMA runs only HRAM and I/O are reachable,
+    // reads on the bus DMA is using see the byte in flight
     pub fn read_byte(&self, address: u16) -> u8 {
-        // For test rom
-        if address == HardwareRegister::LY as u16 {
-            return 0x90;
+        if let Some(dma) = &self.dma
+            && address < 0xFF00
+        {
+            return if address < OAM_START && dma.conflicts_with(address) { dma.byte } else { 0xFF };
         }
-        self.data[address as usize]
+        if self.blocked_by_ppu(address) {
+            return 0xFF;
+        }
+        self.read_mapped(address)
     }
 
     pub fn write_byte(&mut self, address: u16, value: u8) {
-        self.data[address as usize] = value;
+        if self.dma.is_some() && address < 0xFF00 {
+            return;
+        }
+        if self.blocked_by_ppu(address) {
+            return;
+        }
+        if address == HardwareRegister::DMA as u16 {
+            self.dma = Some(OamDma::new(value));
+        }
+        self.write_mapped(address, value);
+    }
+
+    // While the LCD is on the PPU owns VRAM during mode 3 and OAM (plus the
+    // unusable area after it) during modes 2 and 3
+    fn blocked_by_ppu(&self, address: u16) -> bool {
+        let lcdc = self.data[HardwareRegister::LCDC as usize];
+        if lcdc & LCDC_ENABLE == 0 {
+            return false;
+        }
+        let mode = self.data[HardwareRegister::STAT as usize] & STAT_MODE;
+        match address {
+            0x8000..=0x9FFF => mode == MODE_DRAWING,
+            0xFE00..=0xFEFF => mode == MODE_OAM_SCAN || mode == MODE_DRAWING,
+            _ => false,
+        }
+    }
+
+    fn read_mapped(&self, address: u16) -> u8 {
+        if let Some(byte) = self.boot_rom.as_ref().and_then(|boot_rom| boot_rom.read(address)) {
+            return byte;
+        }
+        match address {
+            0x0000..=0x7FFF => match (&self.cartridge, &self.mapper) {
+                (Some(cartridge), _) => cartridge.read_rom(address),
+                (None, Some(mapper)) => mapper.read_rom(address),
+                (None, None) => self.data[address as usize],
+            },
+            0xA000..=0xBFFF => match (&self.cartridge, &self.mapper) {
+                (Some(cartridge), _) => cartridge.read_ram(address),
+                (None, Some(mapper)) => mapper.read_ram(address),
+                (None, None) => self.data[address as usize],
+            },
+            0xE000..=0xFDFF => self.data[(address - 0x2000) as usize],
+            0xFEA0..=0xFEFF => self.unusable_region(address),
+            0xFF00 => self.joypad.read(),
+            APU_START..=APU_END => self.apu.read(address) | io_unused_bits(address, self.model.is_cgb()),
+            0xFF01..=0xFF0F | 0xFF40..=0xFF7F => self.data[address as usize] | io_unused_bits(address, self.model.is_cgb()),
+            _ => self.data[address as usize],
+        }
+    }
+
+    // DMG and SGB read zero, later CGB revisions and the AGB repeat the high nibble of the address
+    fn unusable_region(&self, address: u16) -> u8 {
+        if self.model.is_cgb() {
+            let nibble = (address & 0xF0) as u8;
+            nibble | (nibble >> 4)
+        } else {
+            0x00
+        }
+    }
+
+    fn write_mapped(&mut self, address: u16, value: u8) {
+        // Any non zero write to BANK unmaps the boot ROM until the next reset
+        if address == HardwareRegister::BANK as u16 {
+            if value != 0 {
+                self.boot_rom = None;
+            }
+            return;
+        }
+        match address {
+            // Writes to ROM go to the cartridge's bank controller
+            0x0000..=0x7FFF => match (&mut self.cartridge, &mut self.mapper) {
+                (Some(cartridge), _) => cartridge.write_rom(address, value),
+                (None, Some(mapper)) => mapper.write_rom(address, value),
+                (None, None) => self.data[address as usize] = value,
+            },
+            0xA000..=0xBFFF => match (&mut self.cartridge, &mut self.mapper) {
+                (Some(cartridge), _) => cartridge.write_ram(address, value),
+                (None, Some(mapper)) => mapper.write_ram(address, value),
+                (None, None) => self.data[address as usize] = value,
+            },
+            0xE000..=0xFDFF => self.data[(address - 0x2000) as usize] = value,
+            0xFEA0..=0xFEFF => {}
+            0xFF00 => {
+                if self.joypad.write(value) {
+                    self.data[HardwareRegister::IF as usize] |= Interrupt::Joypad.mask();
+                }
+            }
+            APU_START..=APU_END => self.apu.write(address, value),
+            // LY and the STAT mode/coincidence bits are driven by the PPU
+            0xFF44 => {}
+            0xFF41 => {
+                let stat = self.data[address as usize];
+                self.data[address as usize] = (value & 0x78) | (stat & 0x07);
+            }
+            _ => self.data[address as usize] = value,
+        }
+    }
+
+    // Direct VRAM access for the PPU, bypassing the CPU access rules
+    pub fn read_vram(&self, address: u16) -> u8 {
+        self.data[address as usize]
+    }
+
+    // Direct OAM access for the PPU
+    pub fn read_oam(&self, address: u16) -> u8 {
+        self.data[address as usize]
     }
 
     pub fn write_word(&mut self, address: u16, value: u16) {
-        self.data[address as usize] = (value & 0xFF) as u8;
-        self.data[address as usize + 1] = (value >> 8) as u8;
+        self.write_byte(address, (value & 0xFF) as u8);
+        self.write_byte(address.wrapping_add(1), (value >> 8) as u8);
     }
 
     pub fn read_hardware_register(&self, register: HardwareRegister) -> u8 {
+        if register == HardwareRegister::P1 {
+            return self.joypad.read();
+        }
+        if (APU_START..=APU_END).contains(&(register as u16)) {
+            return self.apu.read(register as u16);
+        }
         self.data[register as usize]
     }
 
     pub fn write_hardware_register(&mut self, register: HardwareRegister, value: u8) {
+        if register == HardwareRegister::P1 {
+            self.joypad.write(value);
+            return;
+        }
+        if (APU_START..=APU_END).contains(&(register as u16)) {
+            self.apu.set_register(register as u16, value);
+            return;
+        }
         self.data[register as usize] = value;
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::boot_rom::{CGB_BOOT_ROM_SIZE, DMG_BOOT_ROM_SIZE};
+
+    // Without a cartridge the ROM area is plain memory, so fill it with a marker
+    fn memory_with_rom(byte: u8) -> Memory {
+        let mut memory = Memory::new();
+        for address in 0x0000..0x0900 {
+            memory.write_byte(address, byte);
+        }
+        memory
+    }
+
+    #[test]
+    fn dmg_boot_rom_overlays_the_first_256_bytes() {
+        let mut memory = memory_with_rom(0x11);
+        memory.load_boot_rom(BootRom::from_bytes(vec![0xAA; DMG_BOOT_ROM_SIZE]).unwrap());
+        assert!(memory.boot_rom_mapped());
+        assert_eq!(memory.read_byte(0x0000), 0xAA);
+        assert_eq!(memory.read_byte(0x00FF), 0xAA);
+        assert_eq!(memory.read_byte(0x0100), 0x11);
+        assert_eq!(memory.read_byte(0x0200), 0x11);
+    }
+
+    #[test]
+    fn cgb_boot_rom_skips_the_header() {
+        let mut memory = memory_with_rom(0x11);
+        memory.load_boot_rom(BootRom::from_bytes(vec![0xAA; CGB_BOOT_ROM_SIZE]).unwrap());
+        assert_eq!(memory.read_byte(0x00FF), 0xAA);
+        assert_eq!(memory.read_byte(0x0100), 0x11);
+        assert_eq!(memory.read_byte(0x01FF), 0x11);
+        assert_eq!(memory.read_byte(0x0200), 0xAA);
+        assert_eq!(memory.read_byte(0x08FF), 0xAA);
+    }
+
+    #[test]
+    fn non_zero_write_to_ff50_unmaps_the_boot_rom() {
+        let mut memory = memory_with_rom(0x11);
+        memory.load_boot_rom(BootRom::from_bytes(vec![0xAA; DMG_BOOT_ROM_SIZE]).unwrap());
+        memory.write_byte(HardwareRegister::BANK as u16, 0x00);
+        assert_eq!(memory.read_byte(0x0000), 0xAA);
+
+        memory.write_byte(HardwareRegister::BANK as u16, 0x01);
+        assert!(!memory.boot_rom_mapped());
+        assert_eq!(memory.read_byte(0x0000), 0x11);
+        // It stays unmapped
+        memory.write_byte(HardwareRegister::BANK as u16, 0x00);
+        assert_eq!(memory.read_byte(0x0000), 0x11);
+    }
+
+    #[test]
+    fn boot_rom_size_is_checked() {
+        assert!(BootRom::from_bytes(vec![0; DMG_BOOT_ROM_SIZE]).is_ok_and(|boot_rom| !boot_rom.is_cgb()));
+        assert!(BootRom::from_bytes(vec![0; CGB_BOOT_ROM_SIZE]).is_ok_and(|boot_rom| boot_rom.is_cgb()));
+        assert!(BootRom::from_bytes(vec![0; 0x0200]).is_err());
+    }
+
+    fn start_dma(memory: &mut Memory, page: u8) {
+        for i in 0..OAM_DMA_LENGTH {
+            memory.write_byte(0xC000 + i, i as u8);
+            memory.write_byte(0x8000 + i, 0x80 | i as u8);
+        }
+        memory.write_byte(HardwareRegister::DMA as u16, page);
+    }
+
+    #[test]
+    fn dma_copies_160_bytes_after_a_startup_delay() {
+        let mut memory = Memory::new();
+        start_dma(&mut memory, 0xC0);
+
+        memory.step_dma(4);
+        memory.step_dma(4);
+        assert_eq!(memory.data[(OAM_START + 1) as usize], 0x00);
+        memory.step_dma(4);
+        assert_eq!(memory.data[(OAM_START + 1) as usize], 0x01);
+
+        memory.step_dma(4 * (OAM_DMA_LENGTH - 3));
+        assert_eq!(memory.read_byte(OAM_START), 0xFF);
+        memory.step_dma(4);
+        for i in 0..OAM_DMA_LENGTH {
+            assert_eq!(memory.read_byte(OAM_START + i), i as u8);
+        }
+    }
+
+    #[test]
+    fn dma_from_vram_and_echo_ram() {
+        let mut memory = Memory::new();
+        start_dma(&mut memory, 0x80);
+        memory.step_dma(4 * (OAM_DMA_LENGTH + 1));
+        assert_eq!(memory.read_byte(OAM_START + 5), 0x85);
+
+        start_dma(&mut memory, 0xE0);
+        memory.step_dma(4 * (OAM_DMA_LENGTH + 1));
+        assert_eq!(memory.read_byte(OAM_START + 5), 0x05);
+    }
+
+    #[test]
+    fn cpu_only_reaches_hram_and_io_during_dma() {
+        let mut memory = Memory::new();
+        memory.write_byte(0xFF80, 0x42);
+        start_dma(&mut memory, 0xC0);
+        memory.step_dma(4 * 11);
+
+        assert_eq!(memory.read_byte(0xFF80), 0x42);
+        assert_eq!(memory.read_byte(HardwareRegister::DMA as u16), 0xC0);
+        // The external bus shows the byte DMA just read, VRAM and OAM read FF
+        assert_eq!(memory.read_byte(0x0000), 0x09);
+        assert_eq!(memory.read_byte(0xD000), 0x09);
+        assert_eq!(memory.read_byte(0x8000), 0xFF);
+        assert_eq!(memory.read_byte(OAM_START), 0xFF);
+
+        memory.write_byte(0xC000, 0x99);
+        memory.write_byte(0xFF80, 0x43);
+        memory.step_dma(4 * OAM_DMA_LENGTH);
+        assert_eq!(memory.read_byte(0xC000), 0x00);
+        assert_eq!(memory.read_byte(0xFF80), 0x43);
+    }
+
+    #[test]
+    fn echo_ram_mirrors_work_ram() {
+        let mut memory = Memory::new();
+        for (address, value) in [(0xC000, 0x01), (0xD123, 0x02), (0xDDFF, 0x03)] {
+            memory.write_byte(address, value);
+            assert_eq!(memory.read_byte(address + 0x2000), value);
+        }
+        memory.write_byte(0xFDFF, 0x04);
+        assert_eq!(memory.read_byte(0xDDFF), 0x04);
+        // OAM right after the echo isn't mirrored
+        memory.write_byte(0xFE00, 0x05);
+        assert_eq!(memory.read_byte(0xDE00), 0x00);
+    }
+
+    #[test]
+    fn unusable_region_per_model() {
+        for (model, reads) in [
+            (Model::Dmg, [(0xFEA0, 0x00), (0xFEB5, 0x00), (0xFEFF, 0x00)]),
+            (Model::Cgb, [(0xFEA0, 0xAA), (0xFEB5, 0xBB), (0xFEFF, 0xFF)]),
+        ] {
+            let mut memory = Memory::new();
+            memory.set_model(model);
+            for (address, value) in reads {
+                memory.write_byte(address, 0x12);
+                assert_eq!(memory.read_byte(address), value, "{:?} {:#06X}", model, address);
+            }
+        }
+    }
+
+    #[test]
+    fn unused_io_reads_ff() {
+        let mut memory = Memory::new();
+        let unused = [0xFF03, 0xFF08, 0xFF0E, 0xFF15, 0xFF1F, 0xFF27, 0xFF2F, 0xFF4C, 0xFF57, 0xFF67, 0xFF71, 0xFF78, 0xFF7F];
+        for address in unused {
+            memory.write_byte(address, 0x00);
+            assert_eq!(memory.read_byte(address), 0xFF, "{:#06X}", address);
+        }
+    }
+
+    #[test]
+    fn unused_register_bits_read_1() {
+        // (register, DMG read back after writing 0, CGB read back)
+        let registers = [
+            (HardwareRegister::SC, 0x7E, 0x7C),
+            (HardwareRegister::TAC, 0xF8, 0xF8),
+            (HardwareRegister::IF, 0xE0, 0xE0),
+            (HardwareRegister::NR10, 0x80, 0x80),
+            (HardwareRegister::NR13, 0xFF, 0xFF),
+            (HardwareRegister::NR30, 0x7F, 0x7F),
+            (HardwareRegister::NR32, 0x9F, 0x9F),
+            (HardwareRegister::NR52, 0x70, 0x70),
+            (HardwareRegister::STAT, 0x80, 0x80),
+            (HardwareRegister::BGP, 0x00, 0x00),
+            (HardwareRegister::KEY1, 0xFF, 0x7E),
+            (HardwareRegister::VBK, 0xFF, 0xFE),
+            (HardwareRegister::SVBK, 0xFF, 0xF8),
+        ];
+        for (register, dmg, cgb) in registers {
+            for (model, expected) in [(Model::Dmg, dmg), (Model::Cgb, cgb)] {
+                let mut memory = Memory::new();
+                memory.set_model(model);
+                memory.write_byte(register as u16, 0x00);
+                assert_eq!(memory.read_byte(register as u16), expected, "{:?} on {:?}", register, model);
+            }
+        }
+    }
+
+    fn memory_in_mode(lcdc: u8, mode: u8) -> Memory {
+        let mut memory = Memory::new();
+        memory.write_byte(0x8000, 0x11);
+        memory.write_byte(0xFE00, 0x22);
+        memory.write_hardware_register(HardwareRegister::LCDC, lcdc);
+        memory.write_hardware_register(HardwareRegister::STAT, 0x80 | mode);
+        memory
+    }
+
+    #[test]
+    fn ppu_blocks_vram_in_mode_3_and_oam_in_modes_2_and_3() {
+        // (mode, VRAM free, OAM free)
+        for (mode, vram_free, oam_free) in [(0, true, true), (1, true, true), (2, true, false), (3, false, false)] {
+            let mut memory = memory_in_mode(LCDC_ENABLE, mode);
+            let (vram, oam) = (if vram_free { 0x11 } else { 0xFF }, if oam_free { 0x22 } else { 0xFF });
+            assert_eq!(memory.read_byte(0x8000), vram, "VRAM read in mode {}", mode);
+            assert_eq!(memory.read_byte(0xFE00), oam, "OAM read in mode {}", mode);
+
+            memory.write_byte(0x8000, 0x33);
+            memory.write_byte(0xFE00, 0x44);
+            assert_eq!(memory.read_vram(0x8000), if vram_free { 0x33 } else { 0x11 }, "VRAM write in mode {}", mode);
+            assert_eq!(memory.read_oam(0xFE00), if oam_free { 0x44 } else { 0x22 }, "OAM write in mode {}", mode);
+        }
+    }
+
+    #[test]
+    fn ppu_blocks_nothing_with_the_lcd_off() {
+        for mode in 0..4 {
+            let mut memory = memory_in_mode(0x00, mode);
+            assert_eq!(memory.read_byte(0x8000), 0x11);
+            assert_eq!(memory.read_byte(0xFE00), 0x22);
+            memory.write_byte(0x9FFF, 0x33);
+            memory.write_byte(0xFE9F, 0x44);
+            assert_eq!(memory.read_vram(0x9FFF), 0x33);
+            assert_eq!(memory.read_oam(0xFE9F), 0x44);
+        }
+    }
+
+    #[test]
+    fn ppu_access_rules_leave_other_memory_alone() {
+        let mut memory = memory_in_mode(LCDC_ENABLE, 3);
+        memory.write_byte(0xC000, 0x55);
+        memory.write_byte(0xFF80, 0x66);
+        assert_eq!(memory.read_byte(0xC000), 0x55);
+        assert_eq!(memory.read_byte(0xFF80), 0x66);
+        assert_eq!(memory.read_byte(HardwareRegister::LCDC as u16), LCDC_ENABLE);
+    }
+}
@@ -0,0 +1,250 @@
+use crate::cpu::CPU;
+use crate::memory::Memory;
+use crate::timer::Timer;
+use crate::data::HardwareRegister;
+
+// Wave RAM powers up with a pattern that differs between units, these are typical
+const DMG_WAVE_RAM: [u8; 16] = [0x84, 0x40, 0x43, 0xAA, 0x2D, 0x78, 0x92, 0x3C, 0x60, 0x59, 0x59, 0xB0, 0x34, 0xB8, 0x2E, 0xDA];
+const CGB_WAVE_RAM: [u8; 16] = [0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Model {
+    Dmg0, // Early DMG with the original boot ROM
+    Dmg,
+    Mgb,  // Game Boy Pocket
+    Sgb,
+    Sgb2,
+    Cgb,
+    Agb,  // Game Boy Advance in CGB mode
+}
+
+impl Model {
+    pub fn from_name(name: &str) -> Option<Model> {
+        match name.to_ascii_lowercase().as_str() {
+            "dmg0" => Some(Model::Dmg0),
+            "dmg" => Some(Model::Dmg),
+            "mgb" => Some(Model::Mgb),
+            "sgb" => Some(Model::Sgb),
+            "sgb2" => Some(Model::Sgb2),
+            "cgb" => Some(Model::Cgb),
+            "agb" => Some(Model::Agb),
+            _ => None,
+        }
+    }
+
+    pub fn is_cgb(self) -> bool {
+        matches!(self, Model::Cgb | Model::Agb)
+    }
+
+    pub fn is_sgb(self) -> bool {
+        matches!(self, Model::Sgb | Model::Sgb2)
+    }
+
+    // Internal 16 bit divider when the boot ROM hands over at 0x0100, DIV is the upper byte.
+    // SGB and CGB boot time depends on the cartridge, these are the typical values.
+    fn post_boot_divider(self) -> u16 {
+        match self {
+            Model::Dmg0 => 0x1830,
+            Model::Dmg | Model::Mgb => 0xABCC,
+            Model::Sgb | Model::Sgb2 => 0xD8C4,
+            Model::Cgb | Model::Agb => 0x1EA0,
+        }
+    }
+
+    // CPU registers left by the boot ROM, https://gbdev.io/pandocs/Power_Up_Sequence.html
+    pub fn apply_post_boot_cpu_state(self, cpu: &mut CPU, header_checksum: u8) {
+        // (A, F, B, C, D, E, H, L)
+        let registers = match self {
+            Model::Dmg0 => (0x01, 0x00, 0xFF, 0x13, 0x00, 0xC1, 0x84, 0x03),
+            // H and C are only set when the header checksum is non zero
+            Model::Dmg => (0x01, if header_checksum == 0 { 0x80 } else { 0xB0 }, 0x00, 0x13, 0x00, 0xD8, 0x01, 0x4D),
+            Model::Mgb => (0xFF, if header_checksum == 0 { 0x80 } else { 0xB0 }, 0x00, 0x13, 0x00, 0xD8, 0x01, 0x4D),
+            Model::Sgb => (0x01, 0x00, 0x00, 0x14, 0x00, 0x00, 0xC0, 0x60),
+            Model::Sgb2 => (0xFF, 0x00, 0x00, 0x14, 0x00, 0x00, 0xC0, 0x60),
+            Model::Cgb => (0x11, 0x80, 0x00, 0x00, 0xFF, 0x56, 0x00, 0x0D),
+            Model::Agb => (0x11, 0x00, 0x01, 0x00, 0xFF, 0x56, 0x00, 0x0D),
+        };
+        (cpu.a, cpu.f, cpu.b, cpu.c, cpu.d, cpu.e, cpu.h, cpu.l) = registers;
+        cpu.sp = 0xFFFE;
+        cpu.pc = 0x0100;
+    }
+
+    // I/O registers left by the boot ROM
+    pub fn apply_post_boot_io_state(self, memory: &mut Memory, timer: &mut Timer) {
+        let cgb = self.is_cgb();
+        let registers = [
+            (HardwareRegister::P1, 0xCF),
+            (HardwareRegister::SB, 0x00),
+            (HardwareRegister::SC, if cgb { 0x7F } else { 0x7E }),
+            (HardwareRegister::TIMA, 0x00),
+            (HardwareRegister::TMA, 0x00),
+            (HardwareRegister::TAC, 0xF8),
+            (HardwareRegister::IF, 0xE1),
+            (HardwareRegister::NR10, 0x80),
+            (HardwareRegister::NR11, 0xBF),
+            (HardwareRegister::NR12, 0xF3),
+            (HardwareRegister::NR13, 0xFF),
+            (HardwareRegister::NR14, 0xBF),
+            (HardwareRegister::NR21, 0x3F),
+            (HardwareRegister::NR22, 0x00),
+            (HardwareRegister::NR23, 0xFF),
+            (HardwareRegister::NR24, 0xBF),
+            (HardwareRegister::NR30, 0x7F),
+            (HardwareRegister::NR31, 0xFF),
+            (HardwareRegister::NR32, 0x9F),
+            (HardwareRegister::NR33, 0xFF),
+            (HardwareRegister::NR34, 0xBF),
+            (HardwareRegister::NR41, 0xFF),
+            (HardwareRegister::NR42, 0x00),
+            (HardwareRegister::NR43, 0x00),
+            (HardwareRegister::NR44, 0xBF),
+            (HardwareRegister::NR50, 0x77),
+            (HardwareRegister::NR51, 0xF3),
+            (HardwareRegister::NR52, if self.is_sgb() { 0xF0 } else { 0xF1 }),
+            (HardwareRegister::LCDC, 0x91),
+            (HardwareRegister::STAT, if self == Model::Dmg0 { 0x81 } else { 0x85 }),
+            (HardwareRegister::SCY, 0x00),
+            (HardwareRegister::SCX, 0x00),
+            (HardwareRegister::LY, if self == Model::Dmg0 { 0x91 } else { 0x00 }),
+            (HardwareRegister::LYC, 0x00),
+            (HardwareRegister::DMA, if cgb { 0x00 } else { 0xFF }),
+            (HardwareRegister::BGP, 0xFC),
+            (HardwareRegister::OBP0, 0xFF),
+            (HardwareRegister::OBP1, 0xFF),
+            (HardwareRegister::WY, 0x00),
+            (HardwareRegister::WX, 0x00),
+            (HardwareRegister::KEY1, if cgb { 0x7E } else { 0xFF }),
+            (HardwareRegister::VBK, if cgb { 0xFE } else { 0xFF }),
+            (HardwareRegister::BANK, 0xFF),
+            (HardwareRegister::HDMA1, 0xFF),
+            (HardwareRegister::HDMA2, 0xFF),
+            (HardwareRegister::HDMA3, 0xFF),
+            (HardwareRegister::HDMA4, 0xFF),
+            (HardwareRegister::HDMA5, 0xFF),
+            (HardwareRegister::RP, if cgb { 0x3E } else { 0xFF }),
+            (HardwareRegister::BCPS, if cgb { 0xC0 } else { 0xFF }),
+            (HardwareRegister::BCPD, 0xFF),
+            (HardwareRegister::OCPS, if cgb { 0xC1 } else { 0xFF }),
+            (HardwareRegister::OCPD, 0xFF),
+            (HardwareRegister::OPRI, if cgb { 0x00 } else { 0xFF }),
+            (HardwareRegister::SVBK, if cgb { 0xF8 } else { 0xFF }),
+            (HardwareRegister::IE, 0x00),
+        ];
+        for (register, value) in registers {
+            memory.write_hardware_register(register, value);
+        }
+
+        let wave_ram = if cgb { CGB_WAVE_RAM } else { DMG_WAVE_RAM };
+        for (i, value) in wave_ram.into_iter().enumerate() {
+            memory.apu_mut().set_register(HardwareRegister::WaveRAM as u16 + i as u16, value);
+        }
+
+        timer.set_divider(self.post_boot_divider(), memory);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MODELS: [Model; 7] = [Model::Dmg0, Model::Dmg, Model::Mgb, Model::Sgb, Model::Sgb2, Model::Cgb, Model::Agb];
+
+    fn booted(model: Model) -> (Memory, Timer) {
+        let mut memory = Memory::new();
+        memory.set_model(model);
+        let mut timer = Timer::new(&mut memory);
+        model.apply_post_boot_io_state(&mut memory, &mut timer);
+        (memory, timer)
+    }
+
+    #[test]
+    fn cpu_registers_per_model() {
+        // (model, A, F, B, C, D, E, H, L)
+        let expected = [
+            (Model::Dmg0, 0x01, 0x00, 0xFF, 0x13, 0x00, 0xC1, 0x84, 0x03),
+            (Model::Dmg, 0x01, 0xB0, 0x00, 0x13, 0x00, 0xD8, 0x01, 0x4D),
+            (Model::Mgb, 0xFF, 0xB0, 0x00, 0x13, 0x00, 0xD8, 0x01, 0x4D),
+            (Model::Sgb, 0x01, 0x00, 0x00, 0x14, 0x00, 0x00, 0xC0, 0x60),
+            (Model::Sgb2, 0xFF, 0x00, 0x00, 0x14, 0x00, 0x00, 0xC0, 0x60),
+            (Model::Cgb, 0x11, 0x80, 0x00, 0x00, 0xFF, 0x56, 0x00, 0x0D),
+            (Model::Agb, 0x11, 0x00, 0x01, 0x00, 0xFF, 0x56, 0x00, 0x0D),
+        ];
+        for (model, a, f, b, c, d, e, h, l) in expected {
+            let mut cpu = CPU::new();
+            model.apply_post_boot_cpu_state(&mut cpu, 0x42);
+            assert_eq!((cpu.a, cpu.f, cpu.b, cpu.c, cpu.d, cpu.e, cpu.h, cpu.l), (a, f, b, c, d, e, h, l), "{:?}", model);
+            assert_eq!((cpu.sp, cpu.pc), (0xFFFE, 0x0100), "{:?}", model);
+        }
+    }
+
+    #[test]
+    fn dmg_half_carry_and_carry_follow_the_header_checksum() {
+        for model in [Model::Dmg, Model::Mgb] {
+            let mut cpu = CPU::new();
+            model.apply_post_boot_cpu_state(&mut cpu, 0x00);
+            assert_eq!(cpu.f, 0x80, "{:?}", model);
+        }
+    }
+
+    #[test]
+    fn io_registers_per_model() {
+        // (register, DMG, SGB, CGB) as read back by the CPU
+        let expected = [
+            (HardwareRegister::P1, 0xCF, 0xCF, 0xCF),
+            (HardwareRegister::SC, 0x7E, 0x7E, 0x7F),
+            (HardwareRegister::TAC, 0xF8, 0xF8, 0xF8),
+            (HardwareRegister::IF, 0xE1, 0xE1, 0xE1),
+            (HardwareRegister::NR52, 0xF1, 0xF0, 0xF1),
+            (HardwareRegister::LCDC, 0x91, 0x91, 0x91),
+            (HardwareRegister::STAT, 0x85, 0x85, 0x85),
+            (HardwareRegister::DMA, 0xFF, 0xFF, 0x00),
+            (HardwareRegister::BGP, 0xFC, 0xFC, 0xFC),
+            (HardwareRegister::KEY1, 0xFF, 0xFF, 0x7E),
+            (HardwareRegister::VBK, 0xFF, 0xFF, 0xFE),
+            (HardwareRegister::BANK, 0xFF, 0xFF, 0xFF),
+            (HardwareRegister::HDMA5, 0xFF, 0xFF, 0xFF),
+            (HardwareRegister::RP, 0xFF, 0xFF, 0x3E),
+            (HardwareRegister::BCPS, 0xFF, 0xFF, 0xC0),
+            (HardwareRegister::OCPS, 0xFF, 0xFF, 0xC1),
+            (HardwareRegister::OPRI, 0xFF, 0xFF, 0xFE),
+            (HardwareRegister::SVBK, 0xFF, 0xFF, 0xF8),
+            (HardwareRegister::IE, 0x00, 0x00, 0x00),
+        ];
+        for (model, column) in [(Model::Dmg, 0), (Model::Sgb, 1), (Model::Cgb, 2)] {
+            let (memory, _) = booted(model);
+            for (register, dmg, sgb, cgb) in expected {
+                let value = [dmg, sgb, cgb][column];
+                assert_eq!(memory.read_byte(register as u16), value, "{:?} on {:?}", register, model);
+            }
+        }
+        let (memory, _) = booted(Model::Dmg0);
+        assert_eq!(memory.read_byte(HardwareRegister::STAT as u16), 0x81);
+        assert_eq!(memory.read_byte(HardwareRegister::LY as u16), 0x91);
+    }
+
+    #[test]
+    fn wave_ram_per_model() {
+        for model in MODELS {
+            let (memory, _) = booted(model);
+            let wave_ram: Vec<u8> = (0xFF30..=0xFF3F).map(|address| memory.read_byte(address)).collect();
+            let expected = if model.is_cgb() { CGB_WAVE_RAM } else { DMG_WAVE_RAM };
+            assert_eq!(wave_ram, expected, "{:?}", model);
+        }
+    }
+
+    #[test]
+    fn div_phase_per_model() {
+        for model in MODELS {
+            let (mut memory, mut timer) = booted(model);
+            let divider = model.post_boot_divider();
+            assert_eq!(memory.read_byte(HardwareRegister::DIV as u16), (divider >> 8) as u8, "{:?}", model);
+
+            // DIV ticks exactly when the low byte of the divider wraps
+            let to_next_tick = 0x100 - (divider & 0xFF);
+            timer.step(to_next_tick - 1, &mut memory);
+            assert_eq!(memory.read_byte(HardwareRegister::DIV as u16), (divider >> 8) as u8, "{:?}", model);
+            timer.step(1, &mut memory);
+            assert_eq!(memory.read_byte(HardwareRegister::DIV as u16), (divider >> 8) as u8 + 1, "{:?}", model);
+        }
+    }
+}
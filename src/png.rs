@@ -0,0 +1,478 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+// Minimal PNG writer for 8 bit grayscale images. The image data goes into stored
+// (uncompressed) deflate blocks, which keeps the encoder tiny at the cost of size.
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+const COLOR_TYPE_GRAYSCALE: u8 = 0;
+const MAX_STORED_BLOCK: usize = 0xFFFF;
+
+pub fn write_grayscale<P: AsRef<Path>>(path: P, width: u32, height: u32, pixels: &[u8]) -> io::Result<()> {
+    fs::write(path, encode_grayscale(width, height, pixels))
+}
+
+// `pixels` is row major, one byte per pixel
+pub fn encode_grayscale(width: u32, height: u32, pixels: &[u8]) -> Vec<u8> {
+    assert_eq!(pixels.len(), width as usize * height as usize, "pixel count doesn't match the image size");
+
+    let mut png = SIGNATURE.to_vec();
+
+    let mut ihdr = Vec::with_capacity(13);
+    ihdr.extend_from_slice(&width.to_be_bytes());
+    ihdr.extend_from_slice(&height.to_be_bytes());
+    // Bit depth, color type, compression, filter, interlace
+    ihdr.extend_from_slice(&[8, COLOR_TYPE_GRAYSCALE, 0, 0, 0]);
+    write_chunk(&mut png, b"IHDR", &ihdr);
+
+    // Every scanline starts with its filter type, 0 = none
+    let mut raw = Vec::with_capacity(pixels.len() + height as usize);
+    for row in pixels.chunks(width.max(1) as usize) {
+        raw.push(0);
+        raw.extend_from_slice(row);
+    }
+    write_chunk(&mut png, b"IDAT", &zlib_stored(&raw));
+    write_chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    // CM 8 (deflate) with a 32K window, no preset dictionary, FCHECK makes it a multiple of 31
+    let mut out = vec![0x78, 0x01];
+    let mut blocks = data.chunks(MAX_STORED_BLOCK).peekable();
+    if blocks.peek().is_none() {
+        out.extend_from_slice(&[0x01, 0x00, 0x00, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        // BFINAL, BTYPE 00, then LEN and its one's complement
+        out.push(last as u8);
+        let len = block.len() as u16;
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    const MOD_ADLER: u32 = 65521;
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + byte as u32) % MOD_ADLER;
+        b = (b + a) % MOD_ADLER;
+    }
+    (b << 16) | a
+}
+
+// Reads reference screenshots back for the test ROM harness. Handles what
+// common tools write: 8 bit images of any color type plus low bit depth gray
+// and palette images, no interlacing. Every pixel comes out as one gray byte.
+#[cfg(test)]
+pub mod decode {
+    use std::io;
+
+    use super::SIGNATURE;
+
+    const LENGTH_BASE: [u16; 29] = [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+    const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+    const DISTANCE_BASE: [u16; 30] = [
+        1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+    ];
+    const DISTANCE_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+    const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+    fn invalid(message: &str) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, message)
+    }
+
+    // Returns width, height and the row major gray pixels
+    pub fn decode_grayscale(png: &[u8]) -> io::Result<(u32, u32, Vec<u8>)> {
+        if png.get(..8) != Some(&SIGNATURE[..]) {
+            return Err(invalid("not a PNG"));
+        }
+
+        let (mut width, mut height, mut depth, mut color_type) = (0u32, 0u32, 0u8, 0u8);
+        let mut palette = Vec::new();
+        let mut idat = Vec::new();
+        let mut offset = 8;
+        while offset + 8 <= png.len() {
+            let len = u32::from_be_bytes([png[offset], png[offset + 1], png[offset + 2], png[offset + 3]]) as usize;
+            let data = png.get(offset + 8..offset + 8 + len).ok_or_else(|| invalid("truncated chunk"))?;
+            match &png[offset + 4..offset + 8] {
+                b"IHDR" if len == 13 => {
+                    width = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
+                    height = u32::from_be_bytes([data[4], data[5], data[6], data[7]]);
+                    depth = data[8];
+                    color_type = data[9];
+                    if data[12] != 0 {
+                        return Err(invalid("interlaced PNGs aren't supported"));
+                    }
+                }
+                b"PLTE" => palette = data.chunks(3).map(gray_of).collect(),
+                b"IDAT" => idat.extend_from_slice(data),
+                b"IEND" => break,
+                _ => {}
+            }
+            offset += 12 + len;
+        }
+
+        let channels = match (color_type, depth) {
+            (0 | 3, 1 | 2 | 4 | 8) => 1,
+            (2, 8) => 3,
+            (4, 8) => 2,
+            (6, 8) => 4,
+            _ => return Err(invalid("unsupported color type or bit depth")),
+        };
+        // Skip the two byte zlib header, the Adler-32 trailer is ignored
+        let raw = inflate(idat.get(2..).ok_or_else(|| invalid("missing image data"))?)?;
+
+        let bits_per_pixel = channels * depth as usize;
+        let stride = (width as usize * bits_per_pixel).div_ceil(8);
+        let bytes_per_pixel = bits_per_pixel.div_ceil(8);
+        let mut previous = vec![0u8; stride];
+        let mut pixels = Vec::with_capacity(width as usize * height as usize);
+        for row in raw.chunks(stride + 1).take(height as usize) {
+            if row.len() != stride + 1 {
+                return Err(invalid("truncated image data"));
+            }
+            let mut current = row[1..].to_vec();
+            unfilter(row[0], &mut current, &previous, bytes_per_pixel)?;
+
+            for x in 0..width as usize {
+                let gray = match color_type {
+                    2 | 6 => gray_of(&current[x * channels..x * channels + 3]),
+                    4 => current[x * channels],
+                    _ => {
+                        let per_byte = 8 / depth as usize;
+                        let shift = 8 - depth as usize * (x % per_byte + 1);
+                        let value = (current[x / per_byte] >> shift) & ((1u16 << depth) - 1) as u8;
+                        if color_type == 3 {
+                            *palette.get(value as usize).ok_or_else(|| invalid("palette index out of range"))?
+                        } else {
+                            value * (255 / ((1u16 << depth) - 1)) as u8
+                        }
+                    }
+                };
+                pixels.push(gray);
+            }
+            previous = current;
+        }
+        if pixels.len() != width as usize * height as usize {
+            return Err(invalid("truncated image data"));
+        }
+        Ok((width, height, pixels))
+    }
+
+    fn gray_of(rgb: &[u8]) -> u8 {
+        ((rgb[0] as u16 + rgb[1] as u16 + rgb[2] as u16) / 3) as u8
+    }
+
+    fn unfilter(filter: u8, row: &mut [u8], previous: &[u8], bytes_per_pixel: usize) -> io::Result<()> {
+        for i in 0..row.len() {
+            let left = if i >= bytes_per_pixel { row[i - bytes_per_pixel] } else { 0 };
+            let up = previous[i];
+            let up_left = if i >= bytes_per_pixel { previous[i - bytes_per_pixel] } else { 0 };
+            let predicted = match filter {
+                0 => 0,
+                1 => left,
+                2 => up,
+                3 => ((left as u16 + up as u16) / 2) as u8,
+                4 => paeth(left, up, up_left),
+                _ => return Err(invalid("unknown filter type")),
+            };
+            row[i] = row[i].wrapping_add(predicted);
+        }
+        Ok(())
+    }
+
+    fn paeth(left: u8, up: u8, up_left: u8) -> u8 {
+        let estimate = left as i16 + up as i16 - up_left as i16;
+        let (to_left, to_up, to_up_left) = ((estimate - left as i16).abs(), (estimate - up as i16).abs(), (estimate - up_left as i16).abs());
+        if to_left <= to_up && to_left <= to_up_left {
+            left
+        } else if to_up <= to_up_left {
+            up
+        } else {
+            up_left
+        }
+    }
+
+    struct BitReader<'a> {
+        data: &'a [u8],
+        bit: usize,
+    }
+
+    impl BitReader<'_> {
+        // Deflate packs values starting at the least significant bit
+        fn bits(&mut self, count: u32) -> io::Result<u32> {
+            let mut value = 0;
+            for i in 0..count {
+                let byte = *self.data.get(self.bit / 8).ok_or_else(|| invalid("truncated deflate stream"))?;
+                value |= ((byte >> (self.bit % 8)) as u32 & 1) << i;
+                self.bit += 1;
+            }
+            Ok(value)
+        }
+    }
+
+    // Canonical Huffman code stored as the number of codes per length and the
+    // symbols in code order
+    struct Huffman {
+        counts: [u16; 16],
+        symbols: Vec<u16>,
+    }
+
+    impl Huffman {
+        fn new(lengths: &[u8]) -> Self {
+            let mut counts = [0u16; 16];
+            for &length in lengths {
+                counts[length as usize] += 1;
+            }
+            counts[0] = 0;
+
+            let mut offsets = [0u16; 16];
+            for length in 1..15 {
+                offsets[length + 1] = offsets[length] + counts[length];
+            }
+            let mut symbols = vec![0; lengths.len()];
+            for (symbol, &length) in lengths.iter().enumerate() {
+                if length != 0 {
+                    symbols[offsets[length as usize] as usize] = symbol as u16;
+                    offsets[length as usize] += 1;
+                }
+            }
+            Huffman { counts, symbols }
+        }
+
+        fn decode(&self, bits: &mut BitReader) -> io::Result<u16> {
+            let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+            for length in 1..16 {
+                code |= bits.bits(1)? as i32;
+                let count = self.counts[length] as i32;
+                if code - first < count {
+                    return Ok(self.symbols[(index + code - first) as usize]);
+                }
+                index += count;
+                first = (first + count) << 1;
+                code <<= 1;
+            }
+            Err(invalid("bad Huffman code"))
+        }
+    }
+
+    pub fn inflate(data: &[u8]) -> io::Result<Vec<u8>> {
+        let mut bits = BitReader { data, bit: 0 };
+        let mut out = Vec::new();
+        loop {
+            let last = bits.bits(1)? == 1;
+            match bits.bits(2)? {
+                0 => {
+                    let start = bits.bit.div_ceil(8);
+                    let header = data.get(start..start + 4).ok_or_else(|| invalid("truncated stored block"))?;
+                    let len = u16::from_le_bytes([header[0], header[1]]) as usize;
+                    let block = data.get(start + 4..start + 4 + len).ok_or_else(|| invalid("truncated stored block"))?;
+                    out.extend_from_slice(block);
+                    bits.bit = (start + 4 + len) * 8;
+                }
+                1 => {
+                    let mut lengths = [8u8; 288];
+                    lengths[144..256].fill(9);
+                    lengths[256..280].fill(7);
+                    inflate_block(&mut bits, &mut out, &Huffman::new(&lengths), &Huffman::new(&[5; 30]))?;
+                }
+                2 => {
+                    let (literals, distances) = dynamic_codes(&mut bits)?;
+                    inflate_block(&mut bits, &mut out, &literals, &distances)?;
+                }
+                _ => return Err(invalid("reserved block type")),
+            }
+            if last {
+                return Ok(out);
+            }
+        }
+    }
+
+    fn dynamic_codes(bits: &mut BitReader) -> io::Result<(Huffman, Huffman)> {
+        let literals = bits.bits(5)? as usize + 257;
+        let distances = bits.bits(5)? as usize + 1;
+        let code_lengths = bits.bits(4)? as usize + 4;
+
+        let mut lengths = [0u8; 19];
+        for &symbol in &CODE_LENGTH_ORDER[..code_lengths] {
+            lengths[symbol] = bits.bits(3)? as u8;
+        }
+        let code = Huffman::new(&lengths);
+
+        let mut lengths = Vec::with_capacity(literals + distances);
+        while lengths.len() < literals + distances {
+            let (length, repeat) = match code.decode(bits)? {
+                symbol @ 0..=15 => (symbol as u8, 1),
+                16 => (*lengths.last().ok_or_else(|| invalid("repeat without a length"))?, 3 + bits.bits(2)?),
+                17 => (0, 3 + bits.bits(3)?),
+                _ => (0, 11 + bits.bits(7)?),
+            };
+            lengths.extend(std::iter::repeat_n(length, repeat as usize));
+        }
+        if lengths.len() != literals + distances {
+            return Err(invalid("code lengths overrun"));
+        }
+        Ok((Huffman::new(&lengths[..literals]), Huffman::new(&lengths[literals..])))
+    }
+
+    fn inflate_block(bits: &mut BitReader, out: &mut Vec<u8>, literals: &Huffman, distances: &Huffman) -> io::Result<()> {
+        loop {
+            let symbol = literals.decode(bits)? as usize;
+            match symbol {
+                0..=255 => out.push(symbol as u8),
+                256 => return Ok(()),
+                _ => {
+                    let index = symbol - 257;
+                    let base = *LENGTH_BASE.get(index).ok_or_else(|| invalid("bad length symbol"))?;
+                    let length = base as usize + bits.bits(LENGTH_EXTRA[index] as u32)? as usize;
+                    let index = distances.decode(bits)? as usize;
+                    let base = *DISTANCE_BASE.get(index).ok_or_else(|| invalid("bad distance symbol"))?;
+                    let distance = base as usize + bits.bits(DISTANCE_EXTRA[index] as u32)? as usize;
+                    if distance > out.len() {
+                        return Err(invalid("distance before the start of the output"));
+                    }
+                    for _ in 0..length {
+                        out.push(out[out.len() - distance]);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn be_u32(bytes: &[u8]) -> u32 {
+        u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+    }
+
+    #[test]
+    fn checksums_match_reference_values() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32(b"IEND"), 0xAE42_6082);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+        assert_eq!(adler32(&[]), 1);
+    }
+
+    #[test]
+    fn encodes_signature_chunks_and_crcs() {
+        let pixels: Vec<u8> = (0..6).map(|i| i * 40).collect();
+        let png = encode_grayscale(3, 2, &pixels);
+        assert_eq!(png[..8], SIGNATURE);
+
+        // IHDR
+        assert_eq!(be_u32(&png[8..12]), 13);
+        assert_eq!(&png[12..16], b"IHDR");
+        assert_eq!(png[16..29], [0, 0, 0, 3, 0, 0, 0, 2, 8, COLOR_TYPE_GRAYSCALE, 0, 0, 0]);
+        assert_eq!(be_u32(&png[29..33]), crc32(&png[12..29]));
+
+        // IDAT holds one stored deflate block of the filtered rows
+        let idat_len = be_u32(&png[33..37]) as usize;
+        assert_eq!(&png[37..41], b"IDAT");
+        let zlib = &png[41..41 + idat_len];
+        assert_eq!(be_u32(&png[41 + idat_len..]), crc32(&png[37..41 + idat_len]));
+        assert_eq!(u16::from_be_bytes([zlib[0], zlib[1]]) % 31, 0);
+        let raw = [0, 0, 40, 80, 0, 120, 160, 200];
+        assert_eq!(zlib[2..7], [0x01, 8, 0, !8, 0xFF]);
+        assert_eq!(zlib[7..15], raw);
+        assert_eq!(be_u32(&zlib[15..]), adler32(&raw));
+
+        // IEND
+        assert_eq!(png[png.len() - 12..], [0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xAE, 0x42, 0x60, 0x82]);
+    }
+
+    #[test]
+    fn splits_large_images_into_stored_blocks() {
+        let pixels = vec![0x80; 300 * 300];
+        let png = encode_grayscale(300, 300, &pixels);
+        let idat_len = be_u32(&png[33..37]) as usize;
+        let zlib = &png[41..41 + idat_len];
+        let raw_len = 301 * 300;
+
+        let mut offset = 2;
+        let mut total = 0;
+        loop {
+            let last = zlib[offset] & 1 != 0;
+            let len = u16::from_le_bytes([zlib[offset + 1], zlib[offset + 2]]) as usize;
+            let nlen = u16::from_le_bytes([zlib[offset + 3], zlib[offset + 4]]) as usize;
+            assert_eq!(len ^ 0xFFFF, nlen);
+            total += len;
+            offset += 5 + len;
+            if last {
+                break;
+            }
+        }
+        assert_eq!(total, raw_len);
+        assert_eq!(offset + 4, zlib.len());
+    }
+
+    fn from_hex(hex: &str) -> Vec<u8> {
+        (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap()).collect()
+    }
+
+    #[test]
+    fn inflates_fixed_and_dynamic_blocks() {
+        // zlib.compress(..., 9) output, skipping the header
+        let fixed = from_hex("78dacb48cdc9c957c8402701680308b1");
+        assert_eq!(decode::inflate(&fixed[2..]).unwrap(), b"hello hello hello hello");
+
+        let dynamic = from_hex(concat!(
+            "78da1d4fc90d4451086a88c373c125f4dfd7f8e742080ae8c393c194702d425648394145a1948b562546635805ecc913768686b9da61a183545c",
+            "0c55c74b4b582b6e7dd40fb632873f31e0a62db82baf32b4064fe5e9d49e52e283b7063e2ac257de88a71984898970b9234273f7a6ca10772c11",
+            "253b7ed6429cf77095837c0a479aecb8ebfebbd640a68648aa1759eae3ad3e7dfeb89fc2f74d69df26fd73312e80f965915f2eebeb607f7d9caf",
+            "9bab1f15804556",
+        ));
+        let expected: String = (0..60).map(|i| format!("{},{};", i * i % 97, i)).collect();
+        assert_eq!(decode::inflate(&dynamic[2..]).unwrap(), expected.as_bytes());
+    }
+
+    #[test]
+    fn decodes_what_the_encoder_writes() {
+        let pixels: Vec<u8> = (0..300 * 300).map(|i| (i % 251) as u8).collect();
+        let png = encode_grayscale(300, 300, &pixels);
+        assert_eq!(decode::decode_grayscale(&png).unwrap(), (300, 300, pixels));
+    }
+
+    #[test]
+    fn decodes_filtered_rgb_rows() {
+        // 3x4 RGB image whose rows use the Sub, Paeth, Average and Up filters
+        let png = [
+            0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A, 0x00, 0x00, 0x00, 0x0D, 0x49, 0x48, 0x44, 0x52, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00,
+            0x00, 0x04, 0x08, 0x02, 0x00, 0x00, 0x00, 0xC4, 0x4F, 0x12, 0x50, 0x00, 0x00, 0x00, 0x26, 0x49, 0x44, 0x41, 0x54, 0x78, 0xDA, 0x63,
+            0x64, 0xF8, 0xCF, 0x10, 0x70, 0x04, 0x84, 0x58, 0x44, 0x18, 0x18, 0x44, 0x18, 0x54, 0x81, 0x88, 0x59, 0xAE, 0x81, 0xC1, 0xE8, 0x91,
+            0x85, 0xD1, 0x23, 0x2F, 0x26, 0x98, 0x98, 0x17, 0x00, 0xAD, 0xA5, 0x07, 0xAB, 0x03, 0x00, 0x50, 0x81, 0x00, 0x00, 0x00, 0x00, 0x49,
+            0x45, 0x4E, 0x44, 0xAE, 0x42, 0x60, 0x82,
+        ];
+        let gray = vec![85, 91, 98, 91, 110, 129, 98, 129, 161, 105, 148, 192];
+        assert_eq!(decode::decode_grayscale(&png).unwrap(), (3, 4, gray));
+    }
+}
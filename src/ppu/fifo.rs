@@ -0,0 +1,559 @@
+use std::collections::VecDeque;
+
+use crate::memory::Memory;
+use crate::data::HardwareRegister;
+
+use super::SCREEN_WIDTH;
+use super::scanline::{
+    Sprite, LCDC_BG_WINDOW_ENABLE, LCDC_BG_TILE_MAP, LCDC_OBJ_ENABLE, LCDC_WINDOW_ENABLE, LCDC_WINDOW_TILE_MAP,
+    palette_shade, tile_address, tile_pixel,
+};
+
+// Dots to fetch an object's tile row once the background fetcher has been paused
+const SPRITE_FETCH_DOTS: u8 = 6;
+// Longest wait for the background fetcher to finish its tile before an object fetch
+const MAX_FETCHER_WAIT: u8 = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FetchStep {
+    Tile,
+    DataLow,
+    DataHigh,
+    Push,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ObjPixel {
+    color: u8,
+    palette: u8,
+    behind_background: bool,
+}
+
+// Dot based mode 3: a background fetcher feeds an 8+ pixel FIFO that shifts one
+// pixel out per dot, object fetches stall the FIFO and the window restarts the
+// fetcher. Registers are read at the moment the hardware would read them, so
+// mid-scanline writes to SCX/SCY/BGP/LCDC show up where they happen.
+pub struct PixelFifo {
+    bg: VecDeque<u8>,
+    obj: VecDeque<ObjPixel>,
+    step: FetchStep,
+    step_dots: u8,
+    fetcher_x: u8,
+    tile: u8,
+    low: u8,
+    high: u8,
+    lx: u8,
+    discard: u8,
+    first_fetch: bool,
+    in_window: bool,
+    sprites: Vec<Sprite>,
+    sprite_fetch: Option<(Sprite, u8)>,
+    waited_tile: Option<u16>, // background tile that already made an object wait
+}
+
+impl PixelFifo {
+    pub fn new() -> Self {
+        PixelFifo {
+            bg: VecDeque::with_capacity(16),
+            obj: VecDeque::with_capacity(8),
+            step: FetchStep::Tile,
+            step_dots: 0,
+            fetcher_x: 0,
+            tile: 0,
+            low: 0,
+            high: 0,
+            lx: 0,
+            discard: 0,
+            first_fetch: true,
+            in_window: false,
+            sprites: Vec::new(),
+            sprite_fetch: None,
+            waited_tile: None,
+        }
+    }
+
+    // Resets the fetcher at the start of mode 3
+    pub fn start_line(&mut self, memory: &Memory, sprites: &[Sprite]) {
+        self.bg.clear();
+        self.obj.clear();
+        self.step = FetchStep::Tile;
+        // The first fetch starts on the last dot of mode 2
+        self.step_dots = 1;
+        self.fetcher_x = 0;
+        self.lx = 0;
+        // The fine scroll is applied by dropping pixels from the first tile
+        self.discard = memory.read_hardware_register(HardwareRegister::SCX) % 8;
+        self.first_fetch = true;
+        self.in_window = false;
+        self.sprites = sprites.to_vec();
+        self.sprite_fetch = None;
+        self.waited_tile = None;
+    }
+
+    pub fn window_drawn(&self) -> bool {
+        self.in_window
+    }
+
+    // Advances mode 3 by one dot, returns true once all 160 pixels are out
+    pub fn tick(&mut self, memory: &Memory, ly: u8, window_triggered: bool, window_line: u8, line: &mut [u8; SCREEN_WIDTH]) -> bool {
+        let lcdc = memory.read_hardware_register(HardwareRegister::LCDC);
+
+        // An object fetch stalls both the fetcher and the pixel output
+        if let Some((sprite, dots)) = self.sprite_fetch.take() {
+            if dots > 1 {
+                self.sprite_fetch = Some((sprite, dots - 1));
+            } else {
+                self.merge_sprite(memory, lcdc, ly, &sprite);
+            }
+            return false;
+        }
+
+        if lcdc & LCDC_OBJ_ENABLE != 0 && self.discard == 0 && self.start_sprite_fetch(memory) {
+            return false;
+        }
+
+        self.check_window(memory, lcdc, window_triggered);
+        self.step_fetcher(memory, lcdc, ly, window_line);
+        // A tile that just arrived at the left edge is replaced before it shifts
+        // out, the window fetch starts on this same dot
+        if self.check_window(memory, lcdc, window_triggered) {
+            self.step_fetcher(memory, lcdc, ly, window_line);
+            return false;
+        }
+
+        let Some(color) = self.bg.pop_front() else {
+            return false;
+        };
+        let obj = self.obj.pop_front();
+
+        if self.discard > 0 {
+            self.discard -= 1;
+            return false;
+        }
+
+        let bg_color = if lcdc & LCDC_BG_WINDOW_ENABLE != 0 { color } else { 0 };
+        let bgp = memory.read_hardware_register(HardwareRegister::BGP);
+        let mut shade = palette_shade(bgp, bg_color);
+
+        if let Some(obj) = obj
+            && obj.color != 0
+            && lcdc & LCDC_OBJ_ENABLE != 0
+            && !(obj.behind_background && bg_color != 0)
+        {
+            shade = palette_shade(obj.palette, obj.color);
+        }
+
+        line[self.lx as usize] = shade;
+        self.lx += 1;
+        self.lx as usize == SCREEN_WIDTH
+    }
+
+    // Returns true when the window takes over from the background
+    fn check_window(&mut self, memory: &Memory, lcdc: u8, window_triggered: bool) -> bool {
+        if self.in_window || !window_triggered || lcdc & LCDC_WINDOW_ENABLE == 0 {
+            return false;
+        }
+        // The window takes over at a pixel about to be shifted out, so at the left
+        // edge it waits for the first background tile and the SCX fine scroll
+        if self.bg.is_empty() || self.discard > 0 {
+            return false;
+        }
+        let wx = memory.read_hardware_register(HardwareRegister::WX);
+        if wx <= 166 && self.lx as u16 + 7 >= wx as u16 {
+            self.in_window = true;
+            self.bg.clear();
+            // With WX below 7 the window starts left of the screen, its first 7 - WX pixels are dropped
+            self.discard = 7u8.saturating_sub(wx);
+            self.step = FetchStep::Tile;
+            self.step_dots = 0;
+            self.fetcher_x = 0;
+            return true;
+        }
+        false
+    }
+
+    fn step_fetcher(&mut self, memory: &Memory, lcdc: u8, ly: u8, window_line: u8) {
+        if self.step == FetchStep::Push {
+            // Only pushes into an empty FIFO
+            if !self.bg.is_empty() {
+                return;
+            }
+            if self.first_fetch {
+                // The first fetch of every line is thrown away
+                self.first_fetch = false;
+            } else {
+                for x in 0..8 {
+                    self.bg.push_back(tile_pixel(self.low, self.high, x));
+                }
+                self.fetcher_x = self.fetcher_x.wrapping_add(1);
+            }
+            self.step = FetchStep::Tile;
+            self.step_dots = 0;
+            return;
+        }
+
+        self.step_dots += 1;
+        if self.step_dots < 2 {
+            return;
+        }
+        self.step_dots = 0;
+
+        match self.step {
+            FetchStep::Tile => {
+                self.tile = memory.read_vram(self.tile_map_address(memory, lcdc, ly, window_line));
+                self.step = FetchStep::DataLow;
+            }
+            FetchStep::DataLow => {
+                self.low = memory.read_vram(self.tile_row_address(memory, lcdc, ly, window_line));
+                self.step = FetchStep::DataHigh;
+            }
+            FetchStep::DataHigh => {
+                self.high = memory.read_vram(self.tile_row_address(memory, lcdc, ly, window_line) + 1);
+                self.step = FetchStep::Push;
+            }
+            FetchStep::Push => {}
+        }
+    }
+
+    fn tile_map_address(&self, memory: &Memory, lcdc: u8, ly: u8, window_line: u8) -> u16 {
+        if self.in_window {
+            let map = if lcdc & LCDC_WINDOW_TILE_MAP != 0 { 0x9C00 } else { 0x9800 };
+            map + (window_line as u16 / 8) * 32 + (self.fetcher_x as u16 & 31)
+        } else {
+            let map = if lcdc & LCDC_BG_TILE_MAP != 0 { 0x9C00 } else { 0x9800 };
+            let scx = memory.read_hardware_register(HardwareRegister::SCX);
+            let scy = memory.read_hardware_register(HardwareRegister::SCY);
+            let y = ly.wrapping_add(scy);
+            map + (y as u16 / 8) * 32 + ((scx as u16 / 8 + self.fetcher_x as u16) & 31)
+        }
+    }
+
+    fn tile_row_address(&self, memory: &Memory, lcdc: u8, ly: u8, window_line: u8) -> u16 {
+        let y = if self.in_window {
+            window_line
+        } else {
+            ly.wrapping_add(memory.read_hardware_register(HardwareRegister::SCY))
+        };
+        tile_address(lcdc, self.tile) + (y as u16 % 8) * 2
+    }
+
+    // Starts fetching the next object that begins at the current pixel. The first
+    // object in a background tile also waits for the fetcher to finish that tile:
+    // 5 dots when the object is aligned to the tile grid, less the further into it.
+    fn start_sprite_fetch(&mut self, memory: &Memory) -> bool {
+        let next = self
+            .sprites
+            .iter()
+            .enumerate()
+            .filter(|(_, sprite)| sprite.x <= self.lx + 8)
+            .min_by_key(|(_, sprite)| (sprite.x, sprite.index))
+            .map(|(i, _)| i);
+        let Some(i) = next else {
+            return false;
+        };
+        let sprite = self.sprites.remove(i);
+
+        let scroll = if self.in_window {
+            // Window tiles are aligned to WX
+            (7u8.wrapping_sub(memory.read_hardware_register(HardwareRegister::WX))) as u16
+        } else {
+            memory.read_hardware_register(HardwareRegister::SCX) as u16
+        };
+        let position = sprite.x as u16 + scroll;
+        let tile = position / 8;
+
+        let wait = if sprite.x == 0 {
+            MAX_FETCHER_WAIT
+        } else if self.waited_tile == Some(tile) {
+            0
+        } else {
+            MAX_FETCHER_WAIT.saturating_sub((position % 8) as u8)
+        };
+        self.waited_tile = Some(tile);
+
+        // This dot is the first of the stall
+        self.sprite_fetch = Some((sprite, SPRITE_FETCH_DOTS + wait - 1));
+        true
+    }
+
+    // Mixes a fetched object row into the object FIFO. Pixels already there came
+    // from a higher priority object and only transparent ones get replaced.
+    fn merge_sprite(&mut self, memory: &Memory, lcdc: u8, ly: u8, sprite: &Sprite) {
+        let palette = sprite.palette(memory);
+        for i in 0..8u8 {
+            let x = self.lx.wrapping_add(i);
+            let color = sprite.pixel(memory, lcdc, ly, x).unwrap_or(0);
+            let pixel = ObjPixel { color, palette, behind_background: sprite.behind_background() };
+            match self.obj.get_mut(i as usize) {
+                Some(existing) if existing.color == 0 => *existing = pixel,
+                Some(_) => {}
+                None => self.obj.push_back(pixel),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::data::HardwareRegister;
+    use crate::memory::Memory;
+    use crate::ppu::{Ppu, Renderer, SCREEN_HEIGHT, SCREEN_WIDTH};
+    use crate::ppu::scanline::{LCDC_BG_WINDOW_ENABLE, LCDC_WINDOW_ENABLE};
+
+    const LCDC_WINDOW_ON: u8 = 0b1111_0001; // window map at 0x9C00, tiles at 0x8000
+
+    // Every pixel of a tile row differs from its neighbours so any horizontal
+    // offset between the renderers shows up
+    fn memory_with_tiles(wx: u8, scx: u8) -> Memory {
+        let mut memory = Memory::new();
+        for tile in 0..4u16 {
+            for row in 0..8u16 {
+                let address = 0x8000 + tile * 16 + row * 2;
+                memory.write_byte(address, 0b1011_0010u8.rotate_left((tile + row) as u32));
+                memory.write_byte(address + 1, 0b0110_0101u8.rotate_right(tile as u32));
+            }
+        }
+        for i in 0..0x400u16 {
+            memory.write_byte(0x9800 + i, (i % 3) as u8);
+            memory.write_byte(0x9C00 + i, (i % 4) as u8 ^ 3);
+        }
+        memory.write_hardware_register(HardwareRegister::BGP, 0xE4);
+        memory.write_hardware_register(HardwareRegister::WY, 20);
+        memory.write_hardware_register(HardwareRegister::WX, wx);
+        memory.write_hardware_register(HardwareRegister::SCX, scx);
+        memory.write_hardware_register(HardwareRegister::SCY, 3);
+        memory.write_hardware_register(HardwareRegister::LCDC, LCDC_WINDOW_ON);
+        memory
+    }
+
+    fn render_frame(renderer: Renderer, wx: u8, scx: u8) -> Vec<u8> {
+        let mut memory = memory_with_tiles(wx, scx);
+        let mut ppu = Ppu::new();
+        ppu.set_renderer(renderer);
+        while ppu.frame_count() == 0 {
+            ppu.step(4, &mut memory);
+        }
+        ppu.framebuffer().to_vec()
+    }
+
+    #[test]
+    fn fifo_matches_scanline_renderer() {
+        for wx in [0, 3, 6, 7, 8, 13, 100, 166, 167] {
+            for scx in [0, 3, 7] {
+                let scanline = render_frame(Renderer::Scanline, wx, scx);
+                let fifo = render_frame(Renderer::Fifo, wx, scx);
+                for y in 0..SCREEN_HEIGHT {
+                    let row = y * SCREEN_WIDTH..(y + 1) * SCREEN_WIDTH;
+                    assert_eq!(fifo[row.clone()], scanline[row], "WX {} SCX {} line {}", wx, scx, y);
+                }
+            }
+        }
+    }
+
+    const LCDC_OBJ_ON: u8 = 0b1001_0011; // LCD, background and objects on, tiles at 0x8000
+
+    // Mode 3 length in dots on line 1 with objects at the given OAM X positions,
+    // all covering line 1
+    fn mode_3_length(scx: u8, objects: &[u8], lcdc: u8, wx: u8) -> u16 {
+        let mut memory = memory_with_tiles(wx, scx);
+        memory.write_hardware_register(HardwareRegister::WY, 0);
+        for (i, &x) in objects.iter().enumerate() {
+            let oam = 0xFE00 + i as u16 * 4;
+            memory.write_byte(oam, 16);
+            memory.write_byte(oam + 1, x);
+        }
+        memory.write_hardware_register(HardwareRegister::LCDC, lcdc);
+        let mut ppu = Ppu::new();
+        ppu.set_renderer(Renderer::Fifo);
+
+        let mut dots = 0;
+        while memory.read_hardware_register(HardwareRegister::LY) < 2 {
+            ppu.step(1, &mut memory);
+            if memory.read_hardware_register(HardwareRegister::LY) == 1 && ppu.mode() == crate::ppu::PpuMode::Drawing {
+                dots += 1;
+            }
+        }
+        dots
+    }
+
+    // Mode 3 lengths from Pan Docs: 172 dots, plus SCX % 8, plus 6 for the window
+    // fetch, plus 6 per object and up to 5 more while the background fetcher
+    // finishes the tile under the object's left edge (11 for an object at X 0)
+    fn object_penalty(x: u8, scx: u8) -> u16 {
+        if x == 0 { 11 } else { 6 + 5u16.saturating_sub((x as u16 + scx as u16) % 8) }
+    }
+
+    #[test]
+    fn mode_3_length_follows_the_fine_scroll() {
+        for scx in [0, 1, 2, 3, 4, 5, 6, 7, 8, 13, 255] {
+            assert_eq!(mode_3_length(scx, &[], LCDC_OBJ_ON, 0), 172 + scx as u16 % 8, "SCX {}", scx);
+        }
+    }
+
+    #[test]
+    fn mode_3_length_with_an_object() {
+        for scx in 0..8 {
+            for x in 0..=167 {
+                let expected = 172 + scx as u16 + object_penalty(x, scx);
+                assert_eq!(mode_3_length(scx, &[x], LCDC_OBJ_ON, 0), expected, "X {} SCX {}", x, scx);
+            }
+            // Objects past the right edge are never fetched
+            assert_eq!(mode_3_length(scx, &[168], LCDC_OBJ_ON, 0), 172 + scx as u16, "SCX {}", scx);
+        }
+    }
+
+    #[test]
+    fn mode_3_length_with_several_objects() {
+        // Only the first object in a background tile waits for the fetcher
+        assert_eq!(mode_3_length(0, &[8, 10], LCDC_OBJ_ON, 0), 172 + 11 + 6);
+        assert_eq!(mode_3_length(0, &[8, 8], LCDC_OBJ_ON, 0), 172 + 11 + 6);
+        assert_eq!(mode_3_length(0, &[8, 16], LCDC_OBJ_ON, 0), 172 + 11 + 11);
+        assert_eq!(mode_3_length(3, &[9, 12], LCDC_OBJ_ON, 0), 175 + 7 + 6);
+        assert_eq!(mode_3_length(3, &[12, 13], LCDC_OBJ_ON, 0), 175 + 6 + 11);
+        // OAM scan stops at 10 objects per line
+        assert_eq!(mode_3_length(0, &[8; 12], LCDC_OBJ_ON, 0), 172 + 11 + 9 * 6);
+        // Objects switched off in LCDC cost nothing
+        assert_eq!(mode_3_length(0, &[8, 16], LCDC_OBJ_ON & !0b10, 0), 172);
+    }
+
+    #[test]
+    fn mode_3_length_with_the_window() {
+        let lcdc = LCDC_OBJ_ON | 0b0110_0000;
+        for scx in [0, 3] {
+            for wx in [7, 8, 50, 166] {
+                assert_eq!(mode_3_length(scx, &[], lcdc, wx), 172 + scx as u16 + 6, "WX {} SCX {}", wx, scx);
+            }
+            // The window pixels left of the screen still shift out
+            for wx in 0..7 {
+                assert_eq!(mode_3_length(scx, &[], lcdc, wx), 172 + scx as u16 + 6 + (7 - wx) as u16, "WX {} SCX {}", wx, scx);
+            }
+            assert_eq!(mode_3_length(scx, &[], lcdc, 167), 172 + scx as u16, "WX 167 SCX {}", scx);
+        }
+        // Inside the window the object's offset is counted from the window's
+        // first tile, 17 pixels in here
+        assert_eq!(mode_3_length(0, &[8 + 60], lcdc, 50), 172 + 6 + 6 + 4);
+    }
+
+    // With SCX 0 and no objects the first pixel leaves the FIFO 12 dots into mode 3,
+    // which starts on dot 81, so pixel x is shifted out on dot 93 + x of its line
+    const FIRST_PIXEL_DOT: u16 = 93;
+    const TRANSPARENT_TILE: u8 = 0x10;
+
+    fn background_memory(scx: u8) -> Memory {
+        let mut memory = memory_with_tiles(0, scx);
+        memory.write_hardware_register(HardwareRegister::LCDC, LCDC_OBJ_ON);
+        memory
+    }
+
+    // Renders a frame with the FIFO renderer, calling `hook` with LY and the dot
+    // within the line after every dot
+    fn render_with_writes(mut memory: Memory, mut hook: impl FnMut(u8, u16, &mut Memory)) -> Vec<u8> {
+        let mut ppu = Ppu::new();
+        ppu.set_renderer(Renderer::Fifo);
+        while ppu.frame_count() == 0 {
+            ppu.step(1, &mut memory);
+            hook(ppu.ly, ppu.dot, &mut memory);
+        }
+        ppu.framebuffer().to_vec()
+    }
+
+    fn line(frame: &[u8], y: usize) -> &[u8] {
+        &frame[y * SCREEN_WIDTH..(y + 1) * SCREEN_WIDTH]
+    }
+
+    // Line 1 of `frame` must show `before` up to and excluding pixel `split` and
+    // `after` from there on
+    fn assert_split(frame: &[u8], before: &[u8], after: &[u8], split: usize) {
+        let expected: Vec<u8> = (0..SCREEN_WIDTH).map(|x| if x < split { before[x] } else { after[x] }).collect();
+        assert_eq!(line(frame, 1), &expected[..]);
+    }
+
+    #[test]
+    fn palette_write_mid_scanline() {
+        let plain = render_with_writes(background_memory(0), |_, _, _| {});
+        let inverted: Vec<u8> = plain.iter().map(|shade| 3 - shade).collect();
+        let frame = render_with_writes(background_memory(0), |ly, dot, memory| {
+            if ly == 1 && dot == FIRST_PIXEL_DOT + 40 {
+                memory.write_hardware_register(HardwareRegister::BGP, 0x1B);
+            }
+        });
+        assert_split(&frame, line(&plain, 1), line(&inverted, 1), 41);
+        assert_eq!(line(&frame, 0), line(&plain, 0));
+        assert_eq!(line(&frame, 2), line(&inverted, 2));
+    }
+
+    #[test]
+    fn background_disabled_mid_scanline() {
+        let plain = render_with_writes(background_memory(0), |_, _, _| {});
+        let frame = render_with_writes(background_memory(0), |ly, dot, memory| {
+            if ly == 1 && dot == FIRST_PIXEL_DOT + 80 {
+                memory.write_hardware_register(HardwareRegister::LCDC, LCDC_OBJ_ON & !LCDC_BG_WINDOW_ENABLE);
+            }
+        });
+        assert_split(&frame, line(&plain, 1), &[0; SCREEN_WIDTH], 81);
+    }
+
+    #[test]
+    fn object_fetch_delays_later_pixels() {
+        let mut memory = background_memory(0);
+        // A transparent object at screen X 40 stalls the FIFO for 11 dots
+        memory.write_byte(0xFE00, 16);
+        memory.write_byte(0xFE01, 8 + 40);
+        memory.write_byte(0xFE02, TRANSPARENT_TILE);
+        let plain = render_with_writes(background_memory(0), |_, _, _| {});
+        let inverted: Vec<u8> = plain.iter().map(|shade| 3 - shade).collect();
+        let frame = render_with_writes(memory, |ly, dot, memory| {
+            if ly == 1 && dot == FIRST_PIXEL_DOT + 45 {
+                memory.write_hardware_register(HardwareRegister::BGP, 0x1B);
+            }
+        });
+        // Pixels 40 to 44 would already be out without the stall
+        assert_split(&frame, line(&plain, 1), line(&inverted, 1), 40);
+    }
+
+    #[test]
+    fn scroll_write_mid_scanline() {
+        let plain = render_with_writes(background_memory(0), |_, _, _| {});
+        let scrolled = render_with_writes(background_memory(8), |_, _, _| {});
+        let frame = render_with_writes(background_memory(0), |ly, dot, memory| {
+            if ly == 1 && dot == FIRST_PIXEL_DOT + 40 {
+                memory.write_hardware_register(HardwareRegister::SCX, 8);
+            }
+        });
+        // The tile holding pixel 40 and the next are already fetched, the map is
+        // read with the new SCX from the tile after that
+        assert_split(&frame, line(&plain, 1), line(&scrolled, 1), 48);
+
+        // The fine scroll is only taken at the start of the line
+        let frame = render_with_writes(background_memory(0), |ly, dot, memory| {
+            if ly == 1 && dot == FIRST_PIXEL_DOT + 40 {
+                memory.write_hardware_register(HardwareRegister::SCX, 3);
+            }
+        });
+        assert_eq!(line(&frame, 1), line(&plain, 1));
+    }
+
+    #[test]
+    fn window_line_counter_pauses_while_the_window_is_off() {
+        let window_memory = || {
+            let mut memory = memory_with_tiles(7, 0);
+            memory.write_hardware_register(HardwareRegister::WY, 0);
+            memory
+        };
+        let plain = render_with_writes(window_memory(), |_, _, _| {});
+        let frame = render_with_writes(window_memory(), |ly, dot, memory| {
+            if dot == 0 && ly == 10 {
+                memory.write_hardware_register(HardwareRegister::LCDC, LCDC_WINDOW_ON & !LCDC_WINDOW_ENABLE);
+            } else if dot == 0 && ly == 20 {
+                memory.write_hardware_register(HardwareRegister::LCDC, LCDC_WINDOW_ON);
+            }
+        });
+        for y in 0..10 {
+            assert_eq!(line(&frame, y), line(&plain, y), "line {}", y);
+        }
+        // The window picks up with its 11th line rather than LY - WY
+        for y in 20..SCREEN_HEIGHT {
+            assert_eq!(line(&frame, y), line(&plain, y - 10), "line {}", y);
+        }
+    }
+}
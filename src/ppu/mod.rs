@@ -0,0 +1,365 @@
+pub mod scanline;
+pub mod fifo;
+
+use scanline::Sprite;
+use fifo::PixelFifo;
+
+use crate::memory::Memory;
+use crate::data::HardwareRegister;
+use crate::interrupts::{Interrupt, request_interrupt};
+
+pub const SCREEN_WIDTH: usize = 160;
+pub const SCREEN_HEIGHT: usize = 144;
+
+const DOTS_PER_LINE: u16 = 456;
+const LINES_PER_FRAME: u8 = 154;
+const OAM_SCAN_DOTS: u16 = 80;
+const DRAWING_DOTS: u16 = 172;
+
+// LCDC bits
+const LCDC_ENABLE: u8 = 0b1000_0000;
+
+// STAT bits
+const STAT_LYC_INTERRUPT: u8 = 0b0100_0000;
+const STAT_OAM_INTERRUPT: u8 = 0b0010_0000;
+const STAT_VBLANK_INTERRUPT: u8 = 0b0001_0000;
+const STAT_HBLANK_INTERRUPT: u8 = 0b0000_1000;
+const STAT_LYC_EQUAL: u8 = 0b0000_0100;
+const STAT_MODE: u8 = 0b0000_0011;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PpuMode {
+    HBlank = 0,
+    VBlank = 1,
+    OamScan = 2,
+    Drawing = 3,
+}
+
+// Scanline draws each line in one go at the end of a fixed length mode 3.
+// Fifo runs the pixel pipeline dot by dot, so mid-line register writes and
+// the variable mode 3 length caused by sprites, SCX and the window are emulated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Renderer {
+    Scanline,
+    Fifo,
+}
+
+impl Renderer {
+    pub fn from_name(name: &str) -> Option<Renderer> {
+        match name.to_ascii_lowercase().as_str() {
+            "scanline" => Some(Renderer::Scanline),
+            "fifo" => Some(Renderer::Fifo),
+            _ => None,
+        }
+    }
+}
+
+// Each scanline is 456 dots: OAM scan (80), drawing (172+) and HBlank for the rest.
+// Lines 144-153 are VBlank.
+pub struct Ppu {
+    mode: PpuMode,
+    dot: u16,
+    ly: u8,
+    stat_line: bool,
+    lcd_just_enabled: bool, // line 0 after the LCD is switched on skips OAM scan
+    window_triggered: bool, // WY matched LY at some point this frame
+    window_line: u8,
+    line_sprites: Vec<Sprite>,
+    renderer: Renderer,
+    line_renderer: Renderer, // renderer latched for the line being drawn
+    fifo: PixelFifo,
+    line: [u8; SCREEN_WIDTH],
+    // Shades 0 (white) - 3 (black), `back` is being drawn and `front` holds the last full frame
+    back: Box<[u8; SCREEN_WIDTH * SCREEN_HEIGHT]>,
+    front: Box<[u8; SCREEN_WIDTH * SCREEN_HEIGHT]>,
+    frame_count: u64,
+}
+
+impl Ppu {
+    pub fn new() -> Self {
+        Ppu {
+            mode: PpuMode::OamScan,
+            dot: 0,
+            ly: 0,
+            stat_line: false,
+            lcd_just_enabled: false,
+            window_triggered: false,
+            window_line: 0,
+            line_sprites: Vec::new(),
+            renderer: Renderer::Scanline,
+            line_renderer: Renderer::Scanline,
+            fifo: PixelFifo::new(),
+            line: [0; SCREEN_WIDTH],
+            back: Box::new([0; SCREEN_WIDTH * SCREEN_HEIGHT]),
+            front: Box::new([0; SCREEN_WIDTH * SCREEN_HEIGHT]),
+            frame_count: 0,
+        }
+    }
+
+    // Takes effect from the next scanline
+    pub fn set_renderer(&mut self, renderer: Renderer) {
+        self.renderer = renderer;
+    }
+
+    pub fn mode(&self) -> PpuMode {
+        self.mode
+    }
+
+    // Last completed frame, row major, one shade (0-3) per pixel
+    pub fn framebuffer(&self) -> &[u8; SCREEN_WIDTH * SCREEN_HEIGHT] {
+        &self.front
+    }
+
+    // Number of frames completed, bumps at the start of every VBlank
+    pub fn frame_count(&self) -> u64 {
+        self.frame_count
+    }
+
+    pub fn step(&mut self, cycles: u16, memory: &mut Memory) {
+        let lcdc = memory.read_hardware_register(HardwareRegister::LCDC);
+        if lcdc & LCDC_ENABLE == 0 {
+            // LCD off: LY is held at 0, the PPU reports HBlank and the screen goes blank
+            if self.dot != 0 || self.ly != 0 {
+                self.front.fill(0);
+            }
+            self.mode = PpuMode::HBlank;
+            self.dot = 0;
+            self.ly = 0;
+            self.stat_line = false;
+            self.lcd_just_enabled = true;
+            self.window_triggered = false;
+            self.window_line = 0;
+            self.update_registers(memory);
+            return;
+        }
+
+        for _ in 0..cycles {
+            self.tick_dot(memory);
+        }
+    }
+
+    fn tick_dot(&mut self, memory: &mut Memory) {
+        self.dot += 1;
+
+        match self.mode {
+            PpuMode::OamScan if self.dot == OAM_SCAN_DOTS => self.start_drawing(memory),
+            // The first line after the LCD is switched on reports mode 0 instead of OAM scan
+            PpuMode::HBlank if self.lcd_just_enabled && self.dot == OAM_SCAN_DOTS => self.start_drawing(memory),
+            PpuMode::Drawing => match self.line_renderer {
+                Renderer::Scanline if self.dot == OAM_SCAN_DOTS + DRAWING_DOTS => {
+                    self.render_line(memory);
+                    self.mode = PpuMode::HBlank;
+                }
+                Renderer::Fifo if self.fifo.tick(memory, self.ly, self.window_triggered, self.window_line, &mut self.line) => {
+                    if self.fifo.window_drawn() {
+                        self.window_line += 1;
+                    }
+                    self.finish_line();
+                    self.mode = PpuMode::HBlank;
+                }
+                _ => {}
+            },
+            _ => {}
+        }
+
+        if self.dot == DOTS_PER_LINE {
+            self.dot = 0;
+            self.ly += 1;
+            if self.ly == LINES_PER_FRAME {
+                self.ly = 0;
+            }
+
+            if self.ly < SCREEN_HEIGHT as u8 {
+                self.mode = PpuMode::OamScan;
+            } else if self.ly == SCREEN_HEIGHT as u8 {
+                self.mode = PpuMode::VBlank;
+                self.window_triggered = false;
+                self.window_line = 0;
+                std::mem::swap(&mut self.front, &mut self.back);
+                self.frame_count += 1;
+                request_interrupt(memory, Interrupt::VBlank);
+            }
+        }
+
+        self.update_registers(memory);
+    }
+
+    fn start_drawing(&mut self, memory: &Memory) {
+        if self.ly == memory.read_hardware_register(HardwareRegister::WY) {
+            self.window_triggered = true;
+        }
+        self.line_sprites = scanline::scan_oam(memory, self.ly);
+        self.line_renderer = self.renderer;
+        if self.line_renderer == Renderer::Fifo {
+            self.fifo.start_line(memory, &self.line_sprites);
+        }
+        self.lcd_just_enabled = false;
+        self.mode = PpuMode::Drawing;
+    }
+
+    fn render_line(&mut self, memory: &Memory) {
+        let mut colors = [0u8; SCREEN_WIDTH];
+        scanline::render_background_line(memory, self.ly, self.window_triggered, &mut self.window_line, &mut colors, &mut self.line);
+        scanline::render_sprite_line(memory, self.ly, &self.line_sprites, &colors, &mut self.line);
+        self.finish_line();
+    }
+
+    fn finish_line(&mut self) {
+        let start = self.ly as usize * SCREEN_WIDTH;
+        self.back[start..start + SCREEN_WIDTH].copy_from_slice(&self.line);
+    }
+
+    // Writes LY and the STAT mode/coincidence bits, and raises the STAT interrupt
+    // on a rising edge of the combined STAT line
+    fn update_registers(&mut self, memory: &mut Memory) {
+        memory.write_hardware_register(HardwareRegister::LY, self.ly);
+
+        let lyc = memory.read_hardware_register(HardwareRegister::LYC);
+        let mut stat = memory.read_hardware_register(HardwareRegister::STAT);
+        stat &= !(STAT_LYC_EQUAL | STAT_MODE);
+        stat |= self.mode as u8;
+        if self.ly == lyc {
+            stat |= STAT_LYC_EQUAL;
+        }
+        memory.write_hardware_register(HardwareRegister::STAT, stat);
+
+        let lcd_on = memory.read_hardware_register(HardwareRegister::LCDC) & LCDC_ENABLE != 0;
+        let stat_line = lcd_on
+            && ((stat & STAT_LYC_EQUAL != 0 && stat & STAT_LYC_INTERRUPT != 0)
+                || (self.mode == PpuMode::HBlank && !self.lcd_just_enabled && stat & STAT_HBLANK_INTERRUPT != 0)
+                || (self.mode == PpuMode::VBlank && stat & STAT_VBLANK_INTERRUPT != 0)
+                || (self.mode == PpuMode::OamScan && stat & STAT_OAM_INTERRUPT != 0));
+
+        if stat_line && !self.stat_line {
+            request_interrupt(memory, Interrupt::LCDStat);
+        }
+        self.stat_line = stat_line;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LCDC_ON: u8 = 0b1001_0001; // LCD and background on, tiles at 0x8000
+
+    // Tile 0 fills the background with color 3
+    fn memory_with_background() -> Memory {
+        let mut memory = Memory::new();
+        for address in 0x8000..0x8010 {
+            memory.write_byte(address, 0xFF);
+        }
+        memory.write_hardware_register(HardwareRegister::BGP, 0xE4);
+        memory
+    }
+
+    fn stat_mode(memory: &Memory) -> u8 {
+        memory.read_hardware_register(HardwareRegister::STAT) & STAT_MODE
+    }
+
+    fn stat_interrupt_requested(memory: &mut Memory) -> bool {
+        let flags = memory.read_hardware_register(HardwareRegister::IF);
+        memory.write_hardware_register(HardwareRegister::IF, flags & !Interrupt::LCDStat.mask());
+        flags & Interrupt::LCDStat.mask() != 0
+    }
+
+    #[test]
+    fn modes_across_a_line() {
+        let mut memory = memory_with_background();
+        memory.write_hardware_register(HardwareRegister::LCDC, LCDC_ON);
+        let mut ppu = Ppu::new();
+
+        ppu.step(OAM_SCAN_DOTS - 1, &mut memory);
+        assert_eq!(ppu.mode(), PpuMode::OamScan);
+        ppu.step(1, &mut memory);
+        assert_eq!(ppu.mode(), PpuMode::Drawing);
+        ppu.step(DRAWING_DOTS, &mut memory);
+        assert_eq!(ppu.mode(), PpuMode::HBlank);
+        assert_eq!(stat_mode(&memory), PpuMode::HBlank as u8);
+        ppu.step(DOTS_PER_LINE - OAM_SCAN_DOTS - DRAWING_DOTS, &mut memory);
+        assert_eq!(ppu.mode(), PpuMode::OamScan);
+        assert_eq!(memory.read_hardware_register(HardwareRegister::LY), 1);
+    }
+
+    #[test]
+    fn first_line_after_lcd_on_skips_oam_scan() {
+        let mut memory = memory_with_background();
+        let mut ppu = Ppu::new();
+        memory.write_hardware_register(HardwareRegister::LCDC, LCDC_ON);
+        ppu.step(DOTS_PER_LINE * 10, &mut memory);
+        memory.write_hardware_register(HardwareRegister::LCDC, 0);
+        ppu.step(4, &mut memory);
+        assert_eq!(memory.read_hardware_register(HardwareRegister::LY), 0);
+
+        memory.write_hardware_register(HardwareRegister::LCDC, LCDC_ON);
+        ppu.step(OAM_SCAN_DOTS - 1, &mut memory);
+        assert_eq!(ppu.mode(), PpuMode::HBlank);
+        ppu.step(1, &mut memory);
+        assert_eq!(ppu.mode(), PpuMode::Drawing);
+        ppu.step(DRAWING_DOTS, &mut memory);
+        assert_eq!(ppu.mode(), PpuMode::HBlank);
+
+        // Line 0 was drawn and shows up once the frame completes
+        for _ in 0..SCREEN_HEIGHT {
+            ppu.step(DOTS_PER_LINE, &mut memory);
+        }
+        assert_eq!(ppu.mode(), PpuMode::VBlank);
+        assert!(ppu.framebuffer()[..SCREEN_WIDTH].iter().all(|&shade| shade == 3));
+    }
+
+    #[test]
+    fn no_hblank_interrupt_when_the_lcd_turns_on() {
+        let mut memory = memory_with_background();
+        let mut ppu = Ppu::new();
+        ppu.step(4, &mut memory);
+        memory.write_hardware_register(HardwareRegister::STAT, STAT_HBLANK_INTERRUPT);
+        memory.write_hardware_register(HardwareRegister::LYC, 0xFF);
+        memory.write_hardware_register(HardwareRegister::LCDC, LCDC_ON);
+
+        ppu.step(OAM_SCAN_DOTS, &mut memory);
+        assert!(!stat_interrupt_requested(&mut memory));
+        ppu.step(DRAWING_DOTS, &mut memory);
+        assert!(stat_interrupt_requested(&mut memory));
+    }
+
+    #[test]
+    fn stat_interrupt_fires_on_rising_edge_only() {
+        let mut memory = memory_with_background();
+        memory.write_hardware_register(HardwareRegister::STAT, STAT_LYC_INTERRUPT | STAT_HBLANK_INTERRUPT);
+        memory.write_hardware_register(HardwareRegister::LYC, 1);
+        memory.write_hardware_register(HardwareRegister::LCDC, LCDC_ON);
+        let mut ppu = Ppu::new();
+
+        ppu.step(OAM_SCAN_DOTS + DRAWING_DOTS, &mut memory);
+        assert!(stat_interrupt_requested(&mut memory)); // HBlank on line 0
+
+        // LY=LYC at the start of line 1 takes over from HBlank, so the line never drops
+        ppu.step(DOTS_PER_LINE - OAM_SCAN_DOTS - DRAWING_DOTS, &mut memory);
+        assert!(!stat_interrupt_requested(&mut memory));
+        ppu.step(OAM_SCAN_DOTS + DRAWING_DOTS, &mut memory);
+        assert_eq!(ppu.mode(), PpuMode::HBlank);
+        assert!(!stat_interrupt_requested(&mut memory));
+
+        // Line 2 drops it during OAM scan, so its HBlank is a new edge
+        ppu.step(DOTS_PER_LINE - OAM_SCAN_DOTS - DRAWING_DOTS, &mut memory);
+        assert!(!stat_interrupt_requested(&mut memory));
+        ppu.step(OAM_SCAN_DOTS + DRAWING_DOTS, &mut memory);
+        assert!(stat_interrupt_requested(&mut memory));
+    }
+
+    #[test]
+    fn lcd_off_resets_the_window() {
+        let mut memory = memory_with_background();
+        memory.write_hardware_register(HardwareRegister::WY, 0);
+        memory.write_hardware_register(HardwareRegister::LCDC, LCDC_ON | 0b0010_0000);
+        let mut ppu = Ppu::new();
+        ppu.step(DOTS_PER_LINE * 3, &mut memory);
+        assert!(ppu.window_triggered);
+        assert_eq!(ppu.window_line, 3);
+
+        memory.write_hardware_register(HardwareRegister::LCDC, 0);
+        ppu.step(4, &mut memory);
+        assert!(!ppu.window_triggered);
+        assert_eq!(ppu.window_line, 0);
+    }
+}
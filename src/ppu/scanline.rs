@@ -0,0 +1,204 @@
+use crate::memory::Memory;
+use crate::data::HardwareRegister;
+
+use super::SCREEN_WIDTH;
+
+// LCDC bits
+pub const LCDC_BG_WINDOW_ENABLE: u8 = 0b0000_0001;
+pub const LCDC_BG_TILE_MAP: u8 = 0b0000_1000;
+pub const LCDC_TILE_DATA: u8 = 0b0001_0000;
+pub const LCDC_WINDOW_ENABLE: u8 = 0b0010_0000;
+pub const LCDC_WINDOW_TILE_MAP: u8 = 0b0100_0000;
+
+// Address of the 16 byte tile for a tile map entry. With LCDC bit 4 set tiles are
+// indexed unsigned from 0x8000, otherwise signed from 0x9000 (the "0x8800 method")
+pub fn tile_address(lcdc: u8, tile: u8) -> u16 {
+    if lcdc & LCDC_TILE_DATA != 0 {
+        0x8000 + tile as u16 * 16
+    } else {
+        (0x9000i32 + (tile as i8) as i32 * 16) as u16
+    }
+}
+
+// Colour index (0-3) of one pixel in a tile row
+pub fn tile_pixel(low: u8, high: u8, x: u8) -> u8 {
+    let bit = 7 - x;
+    (((high >> bit) & 1) << 1) | ((low >> bit) & 1)
+}
+
+// Maps a colour index through BGP/OBP0/OBP1 to a shade (0 white - 3 black)
+pub fn palette_shade(palette: u8, color: u8) -> u8 {
+    (palette >> (color * 2)) & 0x03
+}
+
+// Renders the background and window for line `ly`. Writes the raw colour
+// indices into `colors` (needed for sprite priority) and the shades into `line`.
+// `window_line` is the window's internal line counter, it only advances on
+// lines where the window was drawn.
+pub fn render_background_line(
+    memory: &Memory,
+    ly: u8,
+    window_triggered: bool,
+    window_line: &mut u8,
+    colors: &mut [u8; SCREEN_WIDTH],
+    line: &mut [u8; SCREEN_WIDTH],
+) {
+    let lcdc = memory.read_hardware_register(HardwareRegister::LCDC);
+    let bgp = memory.read_hardware_register(HardwareRegister::BGP);
+
+    // On DMG clearing bit 0 blanks both background and window
+    if lcdc & LCDC_BG_WINDOW_ENABLE == 0 {
+        colors.fill(0);
+        line.fill(palette_shade(bgp, 0));
+        return;
+    }
+
+    let scx = memory.read_hardware_register(HardwareRegister::SCX);
+    let scy = memory.read_hardware_register(HardwareRegister::SCY);
+    let wx = memory.read_hardware_register(HardwareRegister::WX);
+
+    let bg_map = if lcdc & LCDC_BG_TILE_MAP != 0 { 0x9C00 } else { 0x9800 };
+    let window_map = if lcdc & LCDC_WINDOW_TILE_MAP != 0 { 0x9C00 } else { 0x9800 };
+    let window_visible = lcdc & LCDC_WINDOW_ENABLE != 0 && window_triggered && wx <= 166;
+
+    for x in 0..SCREEN_WIDTH as u8 {
+        let in_window = window_visible && x as u16 + 7 >= wx as u16;
+        let (map, map_x, map_y) = if in_window {
+            (window_map, x + 7 - wx, *window_line)
+        } else {
+            (bg_map, x.wrapping_add(scx), ly.wrapping_add(scy))
+        };
+
+        let tile_index = memory.read_vram(map + (map_y as u16 / 8) * 32 + map_x as u16 / 8);
+        let row_address = tile_address(lcdc, tile_index) + (map_y as u16 % 8) * 2;
+        let low = memory.read_vram(row_address);
+        let high = memory.read_vram(row_address + 1);
+
+        let color = tile_pixel(low, high, map_x % 8);
+        colors[x as usize] = color;
+        line[x as usize] = palette_shade(bgp, color);
+    }
+
+    if window_visible {
+        *window_line += 1;
+    }
+}
+
+pub const LCDC_OBJ_ENABLE: u8 = 0b0000_0010;
+pub const LCDC_OBJ_SIZE: u8 = 0b0000_0100;
+
+// OAM attribute bits
+const OBJ_BG_PRIORITY: u8 = 0b1000_0000;
+const OBJ_Y_FLIP: u8 = 0b0100_0000;
+const OBJ_X_FLIP: u8 = 0b0010_0000;
+const OBJ_PALETTE: u8 = 0b0001_0000;
+
+const OAM_START: u16 = 0xFE00;
+const OAM_ENTRIES: u16 = 40;
+pub const MAX_SPRITES_PER_LINE: usize = 10;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Sprite {
+    pub y: u8, // screen Y + 16
+    pub x: u8, // screen X + 8
+    pub tile: u8,
+    pub flags: u8,
+    pub index: u8, // position in OAM
+}
+
+impl Sprite {
+    // Colour index of the sprite pixel at screen column `x` on line `ly`, if it covers it
+    pub fn pixel(&self, memory: &Memory, lcdc: u8, ly: u8, x: u8) -> Option<u8> {
+        let column = (x as i16 + 8) - self.x as i16;
+        if !(0..8).contains(&column) {
+            return None;
+        }
+
+        let height = sprite_height(lcdc);
+        let mut row = ly as u16 + 16 - self.y as u16;
+        if self.flags & OBJ_Y_FLIP != 0 {
+            row = height as u16 - 1 - row;
+        }
+        // 8x16 sprites ignore bit 0 of the tile index
+        let tile = if height == 16 { self.tile & 0xFE } else { self.tile };
+
+        let row_address = 0x8000 + tile as u16 * 16 + row * 2;
+        let low = memory.read_vram(row_address);
+        let high = memory.read_vram(row_address + 1);
+        let column = if self.flags & OBJ_X_FLIP != 0 { 7 - column } else { column } as u8;
+
+        Some(tile_pixel(low, high, column))
+    }
+
+    pub fn palette(&self, memory: &Memory) -> u8 {
+        if self.flags & OBJ_PALETTE != 0 {
+            memory.read_hardware_register(HardwareRegister::OBP1)
+        } else {
+            memory.read_hardware_register(HardwareRegister::OBP0)
+        }
+    }
+
+    pub fn behind_background(&self) -> bool {
+        self.flags & OBJ_BG_PRIORITY != 0
+    }
+}
+
+pub fn sprite_height(lcdc: u8) -> u8 {
+    if lcdc & LCDC_OBJ_SIZE != 0 { 16 } else { 8 }
+}
+
+// Mode 2 OAM scan: the first 10 sprites in OAM order that overlap line `ly`.
+// X doesn't matter here, off screen sprites still use up a slot.
+pub fn scan_oam(memory: &Memory, ly: u8) -> Vec<Sprite> {
+    let lcdc = memory.read_hardware_register(HardwareRegister::LCDC);
+    let height = sprite_height(lcdc) as u16;
+    let line = ly as u16 + 16;
+
+    let mut sprites = Vec::with_capacity(MAX_SPRITES_PER_LINE);
+    for index in 0..OAM_ENTRIES {
+        let address = OAM_START + index * 4;
+        let y = memory.read_oam(address);
+        if line >= y as u16 && line < y as u16 + height {
+            sprites.push(Sprite {
+                y,
+                x: memory.read_oam(address + 1),
+                tile: memory.read_oam(address + 2),
+                flags: memory.read_oam(address + 3),
+                index: index as u8,
+            });
+            if sprites.len() == MAX_SPRITES_PER_LINE {
+                break;
+            }
+        }
+    }
+    sprites
+}
+
+// Draws the sprites selected by the OAM scan over the background line.
+// On DMG the sprite with the smaller X wins, ties go to the lower OAM index.
+pub fn render_sprite_line(memory: &Memory, ly: u8, sprites: &[Sprite], colors: &[u8; SCREEN_WIDTH], line: &mut [u8; SCREEN_WIDTH]) {
+    let lcdc = memory.read_hardware_register(HardwareRegister::LCDC);
+    if lcdc & LCDC_OBJ_ENABLE == 0 {
+        return;
+    }
+
+    let mut ordered = sprites.to_vec();
+    ordered.sort_by_key(|sprite| (sprite.x, sprite.index));
+
+    for x in 0..SCREEN_WIDTH as u8 {
+        let pixel = ordered
+            .iter()
+            .find_map(|sprite| match sprite.pixel(memory, lcdc, ly, x) {
+                Some(color) if color != 0 => Some((sprite, color)),
+                _ => None,
+            });
+
+        if let Some((sprite, color)) = pixel {
+            // BG-over-OBJ only hides the sprite behind background colours 1-3
+            if sprite.behind_background() && colors[x as usize] != 0 {
+                continue;
+            }
+            line[x as usize] = palette_shade(sprite.palette(memory), color);
+        }
+    }
+}
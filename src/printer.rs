@@ -0,0 +1,470 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::png;
+use crate::ppu::SCREEN_WIDTH;
+use crate::ppu::scanline::{palette_shade, tile_pixel};
+use crate::serial::SerialDevice;
+
+// Game Boy Printer on the serial port. The Game Boy drives the clock and sends
+// packets of the form
+//   0x88 0x33, command, compression, length (u16 LE), data, checksum (u16 LE), 0x00 0x00
+// The printer answers 0x00 to everything except the last two bytes, where it
+// sends 0x81 (printer present) and its status. The checksum is the 16 bit sum of
+// everything from the command to the end of the data.
+
+const MAGIC: [u8; 2] = [0x88, 0x33];
+const ALIVE: u8 = 0x81;
+
+// Commands
+const CMD_INIT: u8 = 0x01;
+const CMD_PRINT: u8 = 0x02;
+const CMD_DATA: u8 = 0x04;
+const CMD_STATUS: u8 = 0x0F;
+
+// Status bits
+const STATUS_CHECKSUM_ERROR: u8 = 0b0000_0001;
+const STATUS_PRINTING: u8 = 0b0000_0010;
+const STATUS_IMAGE_FULL: u8 = 0b0000_0100;
+const STATUS_UNPROCESSED: u8 = 0b0000_1000;
+const STATUS_PACKET_ERROR: u8 = 0b0001_0000;
+
+// The printer holds 8 KiB of 2bpp tiles, 9 bands of 20x2 tiles
+const BAND_BYTES: usize = 640;
+const BAND_HEIGHT: usize = 16;
+const MAX_BANDS: usize = 9;
+const MAX_PACKET_DATA: usize = BAND_BYTES;
+
+// A palette byte of 0 is treated like the usual 0xE4 by the printer
+const DEFAULT_PALETTE: u8 = 0xE4;
+
+// Paper moves roughly one band per line feed
+const FEED_ROWS: usize = BAND_HEIGHT;
+// How long the print head stays busy after a print command
+const PRINT_CYCLES: u32 = 4_194_304;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PacketState {
+    Magic1,
+    Magic2,
+    Command,
+    Compression,
+    LengthLow,
+    LengthHigh,
+    Data,
+    ChecksumLow,
+    ChecksumHigh,
+    Alive,
+    Status,
+}
+
+pub struct Printer {
+    out_dir: PathBuf,
+    state: PacketState,
+    command: u8,
+    compressed: bool,
+    length: u16,
+    data: Vec<u8>,
+    checksum: u16,
+    received_checksum: u16,
+    status: u8,
+    image: Vec<u8>, // raw 2bpp tile data received since the last init or print
+    paper: Vec<u8>, // shades 0-3 of the strip printed so far, 160 pixels wide
+    busy_cycles: u32,
+}
+
+impl Printer {
+    // Every print job is written to `out_dir` as print_NNN.png, numbered on from
+    // the prints already there
+    pub fn new<P: Into<PathBuf>>(out_dir: P) -> Self {
+        Printer {
+            out_dir: out_dir.into(),
+            state: PacketState::Magic1,
+            command: 0,
+            compressed: false,
+            length: 0,
+            data: Vec::with_capacity(MAX_PACKET_DATA),
+            checksum: 0,
+            received_checksum: 0,
+            status: 0,
+            image: Vec::new(),
+            paper: Vec::new(),
+            busy_cycles: 0,
+        }
+    }
+
+    // Feeds one byte from the Game Boy through the packet state machine and
+    // returns the byte shifted back
+    fn receive(&mut self, byte: u8) -> u8 {
+        match self.state {
+            PacketState::Magic1 => {
+                if byte == MAGIC[0] {
+                    self.state = PacketState::Magic2;
+                }
+            }
+            PacketState::Magic2 => {
+                self.state = match byte {
+                    _ if byte == MAGIC[1] => PacketState::Command,
+                    _ if byte == MAGIC[0] => PacketState::Magic2,
+                    _ => PacketState::Magic1,
+                };
+            }
+            PacketState::Command => {
+                self.command = byte;
+                self.checksum = byte as u16;
+                self.data.clear();
+                self.state = PacketState::Compression;
+            }
+            PacketState::Compression => {
+                self.compressed = byte & 1 != 0;
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                self.state = PacketState::LengthLow;
+            }
+            PacketState::LengthLow => {
+                self.length = byte as u16;
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                self.state = PacketState::LengthHigh;
+            }
+            PacketState::LengthHigh => {
+                self.length |= (byte as u16) << 8;
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                self.state = if self.length == 0 { PacketState::ChecksumLow } else { PacketState::Data };
+            }
+            PacketState::Data => {
+                // Oversized packets are still clocked through, the excess is dropped
+                if self.data.len() < MAX_PACKET_DATA {
+                    self.data.push(byte);
+                }
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                self.length -= 1;
+                if self.length == 0 {
+                    self.state = PacketState::ChecksumLow;
+                }
+            }
+            PacketState::ChecksumLow => {
+                self.received_checksum = byte as u16;
+                self.state = PacketState::ChecksumHigh;
+            }
+            PacketState::ChecksumHigh => {
+                self.received_checksum |= (byte as u16) << 8;
+                self.finish_packet();
+                self.state = PacketState::Alive;
+            }
+            PacketState::Alive => {
+                self.state = PacketState::Status;
+                return ALIVE;
+            }
+            PacketState::Status => {
+                self.state = PacketState::Magic1;
+                return self.status;
+            }
+        }
+        0x00
+    }
+
+    fn finish_packet(&mut self) {
+        if self.received_checksum != self.checksum {
+            self.status |= STATUS_CHECKSUM_ERROR;
+            return;
+        }
+        self.status &= !STATUS_CHECKSUM_ERROR;
+
+        match self.command {
+            CMD_INIT => {
+                self.image.clear();
+                self.status = 0;
+            }
+            CMD_DATA => {
+                // An empty data packet only marks the end of the image
+                if self.data.is_empty() {
+                    return;
+                }
+                let band = if self.compressed { decompress(&self.data) } else { self.data.clone() };
+                if self.image.len() < MAX_BANDS * BAND_BYTES {
+                    self.image.extend_from_slice(&band);
+                }
+                self.status |= STATUS_UNPROCESSED;
+                if self.image.len() >= MAX_BANDS * BAND_BYTES {
+                    self.status |= STATUS_IMAGE_FULL;
+                }
+            }
+            CMD_PRINT => {
+                if self.data.len() < 4 {
+                    self.status |= STATUS_PACKET_ERROR;
+                    return;
+                }
+                let (sheets, margins) = (self.data[0], self.data[1]);
+                let palette = if self.data[2] == 0 { DEFAULT_PALETTE } else { self.data[2] };
+                self.print(sheets, margins >> 4, margins & 0x0F, palette);
+            }
+            CMD_STATUS => {}
+            _ => self.status |= STATUS_PACKET_ERROR,
+        }
+    }
+
+    // Prints the buffered image `sheets` times. A bottom margin feeds the paper
+    // out, which ends the job, prints without one continue on the same strip.
+    fn print(&mut self, sheets: u8, top_margin: u8, bottom_margin: u8, palette: u8) {
+        self.paper.resize(self.paper.len() + top_margin as usize * FEED_ROWS * SCREEN_WIDTH, 0);
+        let rendered = render_bands(&self.image, palette);
+        for _ in 0..sheets {
+            self.paper.extend_from_slice(&rendered);
+        }
+        self.paper.resize(self.paper.len() + bottom_margin as usize * FEED_ROWS * SCREEN_WIDTH, 0);
+
+        if bottom_margin > 0
+            && let Err(err) = self.finish_job()
+        {
+            eprintln!("Printer: {}", err);
+        }
+
+        self.image.clear();
+        self.status = (self.status & !(STATUS_UNPROCESSED | STATUS_IMAGE_FULL)) | STATUS_PRINTING;
+        self.busy_cycles = PRINT_CYCLES;
+    }
+
+    // Writes the paper printed so far to the next PNG
+    pub fn finish_job(&mut self) -> io::Result<()> {
+        if self.paper.is_empty() {
+            return Ok(());
+        }
+        let paper = std::mem::take(&mut self.paper);
+        let gray: Vec<u8> = paper.iter().map(|&shade| 255 - shade * 85).collect();
+        let height = (paper.len() / SCREEN_WIDTH) as u32;
+
+        let path = self.out_dir.join(format!("print_{:03}.png", last_job_number(&self.out_dir) + 1));
+        png::write_grayscale(&path, SCREEN_WIDTH as u32, height, &gray)?;
+        println!("Printed {}", path.display());
+        Ok(())
+    }
+}
+
+impl SerialDevice for Printer {
+    fn exchange(&mut self, outgoing: u8) -> Option<u8> {
+        Some(self.receive(outgoing))
+    }
+
+    fn step(&mut self, cycles: u16, _outgoing: u8, _waiting: bool) -> Option<u8> {
+        if self.busy_cycles > 0 {
+            self.busy_cycles = self.busy_cycles.saturating_sub(cycles as u32);
+            if self.busy_cycles == 0 {
+                self.status &= !STATUS_PRINTING;
+            }
+        }
+        None
+    }
+}
+
+// Paper left in the printer when the emulator shuts down still comes out
+impl Drop for Printer {
+    fn drop(&mut self) {
+        if let Err(err) = self.finish_job() {
+            eprintln!("Printer: {}", err);
+        }
+    }
+}
+
+// Highest NNN of the print_NNN.png files in `dir`, 0 if there are none
+fn last_job_number(dir: &Path) -> u32 {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return 0;
+    };
+    entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let name = entry.file_name();
+            let number = name.to_str()?.strip_prefix("print_")?.strip_suffix(".png")?;
+            number.parse::<u32>().ok()
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+// Runs of the form 0x80 | (n - 2) followed by one byte repeated n times, or
+// n - 1 followed by n literal bytes
+fn decompress(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(BAND_BYTES);
+    let mut i = 0;
+    while i < data.len() {
+        let control = data[i];
+        i += 1;
+        if control & 0x80 != 0 {
+            let count = (control & 0x7F) as usize + 2;
+            if let Some(&byte) = data.get(i) {
+                out.extend(std::iter::repeat_n(byte, count));
+            }
+            i += 1;
+        } else {
+            let count = control as usize + 1;
+            let end = (i + count).min(data.len());
+            out.extend_from_slice(&data[i..end]);
+            i = end;
+        }
+    }
+    out
+}
+
+// Each band is 2 rows of 20 tiles in the usual 2bpp format
+fn render_bands(image: &[u8], palette: u8) -> Vec<u8> {
+    let bands = image.len() / BAND_BYTES;
+    let mut pixels = vec![0u8; bands * BAND_HEIGHT * SCREEN_WIDTH];
+    for (tile_index, tile) in image[..bands * BAND_BYTES].chunks(16).enumerate() {
+        let tile_row = tile_index / 20;
+        let tile_column = tile_index % 20;
+        for row in 0..8 {
+            let (low, high) = (tile[row * 2], tile[row * 2 + 1]);
+            let y = tile_row * 8 + row;
+            for x in 0..8 {
+                pixels[y * SCREEN_WIDTH + tile_column * 8 + x] = palette_shade(palette, tile_pixel(low, high, x as u8));
+            }
+        }
+    }
+    pixels
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rustboy-printer-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    // Sends a whole packet, returns the printer's replies to every byte
+    fn send_packet(printer: &mut Printer, command: u8, compressed: bool, data: &[u8], corrupt: bool) -> Vec<u8> {
+        let mut body = vec![command, compressed as u8];
+        body.extend_from_slice(&(data.len() as u16).to_le_bytes());
+        body.extend_from_slice(data);
+        let mut checksum = body.iter().fold(0u16, |sum, &byte| sum.wrapping_add(byte as u16));
+        if corrupt {
+            checksum ^= 1;
+        }
+
+        let mut packet = MAGIC.to_vec();
+        packet.extend_from_slice(&body);
+        packet.extend_from_slice(&checksum.to_le_bytes());
+        packet.extend_from_slice(&[0x00, 0x00]);
+        packet.into_iter().map(|byte| printer.exchange(byte).unwrap()).collect()
+    }
+
+    fn status_reply(replies: &[u8]) -> u8 {
+        assert!(replies[..replies.len() - 2].iter().all(|&byte| byte == 0x00));
+        assert_eq!(replies[replies.len() - 2], ALIVE);
+        replies[replies.len() - 1]
+    }
+
+    // One band where tile row `n` of every tile uses color n % 4
+    fn striped_band() -> Vec<u8> {
+        let rows = [[0x00, 0x00], [0xFF, 0x00], [0x00, 0xFF], [0xFF, 0xFF]];
+        (0..BAND_BYTES / 2).flat_map(|row| rows[row % 8 % 4]).collect()
+    }
+
+    #[test]
+    fn decompress_runs_and_literals() {
+        assert_eq!(decompress(&[0x81, 0xAA, 0x02, 1, 2, 3]), [0xAA, 0xAA, 0xAA, 1, 2, 3]);
+        assert_eq!(decompress(&[0x80, 0x55, 0x00, 9]), [0x55, 0x55, 9]);
+        assert_eq!(decompress(&[0xFF, 0x11]).len(), 129);
+        // Truncated runs keep what's there
+        assert_eq!(decompress(&[0x03, 1, 2]), [1, 2]);
+        assert_eq!(decompress(&[0x85]), []);
+    }
+
+    #[test]
+    fn compressed_band_matches_uncompressed() {
+        let band = striped_band();
+        let mut compressed = Vec::new();
+        for chunk in band.chunks(2) {
+            compressed.extend_from_slice(&[0x01, chunk[0], chunk[1]]);
+        }
+        assert_eq!(decompress(&compressed), band);
+    }
+
+    #[test]
+    fn replies_and_status() {
+        let dir = temp_dir("status");
+        let mut printer = Printer::new(&dir);
+        assert_eq!(status_reply(&send_packet(&mut printer, CMD_INIT, false, &[], false)), 0);
+        assert_eq!(status_reply(&send_packet(&mut printer, CMD_DATA, false, &striped_band(), false)), STATUS_UNPROCESSED);
+        assert_eq!(status_reply(&send_packet(&mut printer, CMD_STATUS, false, &[], false)), STATUS_UNPROCESSED);
+
+        let status = status_reply(&send_packet(&mut printer, CMD_STATUS, false, &[], true));
+        assert_eq!(status, STATUS_UNPROCESSED | STATUS_CHECKSUM_ERROR);
+        assert_eq!(status_reply(&send_packet(&mut printer, CMD_STATUS, false, &[], false)), STATUS_UNPROCESSED);
+
+        assert_eq!(status_reply(&send_packet(&mut printer, CMD_PRINT, false, &[1, 0x00, 0xE4, 0x40], false)), STATUS_PRINTING);
+        printer.step(u16::MAX, 0, false);
+        assert_eq!(status_reply(&send_packet(&mut printer, CMD_STATUS, false, &[], false)), STATUS_PRINTING);
+        for _ in 0..PRINT_CYCLES / u16::MAX as u32 {
+            printer.step(u16::MAX, 0, false);
+        }
+        assert_eq!(status_reply(&send_packet(&mut printer, CMD_STATUS, false, &[], false)), 0);
+
+        assert_eq!(status_reply(&send_packet(&mut printer, 0x07, false, &[], false)), STATUS_PACKET_ERROR);
+        printer.paper.clear();
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn image_full_after_nine_bands() {
+        let dir = temp_dir("full");
+        let mut printer = Printer::new(&dir);
+        for _ in 0..MAX_BANDS - 1 {
+            send_packet(&mut printer, CMD_DATA, false, &striped_band(), false);
+        }
+        assert_eq!(printer.status & STATUS_IMAGE_FULL, 0);
+        send_packet(&mut printer, CMD_DATA, false, &striped_band(), false);
+        assert_ne!(printer.status & STATUS_IMAGE_FULL, 0);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn render_bands_applies_the_palette() {
+        let band = striped_band();
+        let pixels = render_bands(&band, 0xE4);
+        assert_eq!(pixels.len(), BAND_HEIGHT * SCREEN_WIDTH);
+        for (y, row) in pixels.chunks(SCREEN_WIDTH).enumerate() {
+            assert!(row.iter().all(|&shade| shade == (y % 8 % 4) as u8), "row {}", y);
+        }
+
+        // 0x1B inverts the usual palette
+        let inverted = render_bands(&band, 0x1B);
+        assert!(inverted.iter().zip(&pixels).all(|(&a, &b)| a == 3 - b));
+
+        // A partial band isn't printed
+        assert!(render_bands(&band[..BAND_BYTES - 1], 0xE4).is_empty());
+    }
+
+    #[test]
+    fn zero_palette_prints_like_e4() {
+        let dir = temp_dir("palette");
+        let mut printer = Printer::new(&dir);
+        send_packet(&mut printer, CMD_DATA, false, &striped_band(), false);
+        send_packet(&mut printer, CMD_PRINT, false, &[1, 0x00, 0x00, 0x40], false);
+        let paper = std::mem::take(&mut printer.paper);
+        assert_eq!(paper, render_bands(&striped_band(), 0xE4));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn job_numbers_continue_after_existing_prints() {
+        let dir = temp_dir("numbering");
+        fs::write(dir.join("print_007.png"), []).unwrap();
+        fs::write(dir.join("print_notes.png"), []).unwrap();
+
+        let mut printer = Printer::new(&dir);
+        send_packet(&mut printer, CMD_DATA, false, &striped_band(), false);
+        send_packet(&mut printer, CMD_PRINT, false, &[1, 0x01, 0xE4, 0x40], false);
+        let png = fs::read(dir.join("print_008.png")).unwrap();
+        // 16 rows of paper plus one feed for the bottom margin
+        assert_eq!(png[16..24], [0, 0, 0, 160, 0, 0, 0, 32]);
+
+        send_packet(&mut printer, CMD_DATA, false, &striped_band(), false);
+        send_packet(&mut printer, CMD_PRINT, false, &[1, 0x01, 0xE4, 0x40], false);
+        assert!(dir.join("print_009.png").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,137 @@
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+// Autosave every 5 seconds of emulated time
+pub const AUTOSAVE_INTERVAL: u32 = 4_194_304 * 5;
+
+// Battery backed cartridge RAM persisted to `<rom>.sav`
+pub struct SaveFile {
+    path: PathBuf,
+    last_written: Vec<u8>,
+    cycles_since_flush: u32,
+}
+
+impl SaveFile {
+    pub fn for_rom<P: AsRef<Path>>(rom_path: P) -> Self {
+        SaveFile {
+            path: rom_path.as_ref().with_extension("sav"),
+            last_written: Vec::new(),
+            cycles_since_flush: 0,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // Returns None when there is no save yet
+    pub fn load(&mut self) -> io::Result<Option<Vec<u8>>> {
+        match fs::read(&self.path) {
+            Ok(data) => {
+                self.last_written = data.clone();
+                Ok(Some(data))
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    // Returns true once the autosave interval has elapsed
+    pub fn step(&mut self, cycles: u16) -> bool {
+        self.cycles_since_flush += cycles as u32;
+        self.cycles_since_flush >= AUTOSAVE_INTERVAL
+    }
+
+    // Writes the save if it changed since it was last loaded or written. The
+    // last `timestamp_len` bytes are a timestamp and don't count as a change.
+    pub fn flush(&mut self, data: &[u8], timestamp_len: usize) -> io::Result<()> {
+        self.cycles_since_flush = 0;
+        if without_timestamp(data, timestamp_len) == without_timestamp(&self.last_written, timestamp_len) {
+            return Ok(());
+        }
+        write_atomic(&self.path, data)?;
+        self.last_written = data.to_vec();
+        Ok(())
+    }
+}
+
+fn without_timestamp(data: &[u8], timestamp_len: usize) -> &[u8] {
+    &data[..data.len().saturating_sub(timestamp_len)]
+}
+
+// Write to a temporary file next to the target and rename it over the old save,
+// so a crash mid-write leaves either the old or the new save intact
+fn write_atomic(path: &Path, data: &[u8]) -> io::Result<()> {
+    let mut tmp_name = path.as_os_str().to_owned();
+    tmp_name.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_name);
+
+    let mut file = File::create(&tmp_path)?;
+    file.write_all(data)?;
+    file.sync_all()?;
+    drop(file);
+
+    fs::rename(&tmp_path, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_rom_path(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rustboy-save-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir.join("game.gb")
+    }
+
+    #[test]
+    fn flush_and_load_round_trip() {
+        let rom_path = temp_rom_path("round-trip");
+        let mut save = SaveFile::for_rom(&rom_path);
+        assert_eq!(save.path(), rom_path.with_extension("sav"));
+        assert!(save.load().unwrap().is_none());
+
+        save.flush(&[1, 2, 3, 4], 0).unwrap();
+        assert_eq!(SaveFile::for_rom(&rom_path).load().unwrap(), Some(vec![1, 2, 3, 4]));
+        assert!(!save.path().with_extension("sav.tmp").exists());
+        fs::remove_dir_all(rom_path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn flush_skips_unchanged_data() {
+        let rom_path = temp_rom_path("unchanged");
+        let mut save = SaveFile::for_rom(&rom_path);
+        save.flush(&[1, 2, 3, 4], 0).unwrap();
+        fs::remove_file(save.path()).unwrap();
+
+        save.flush(&[1, 2, 3, 4], 0).unwrap();
+        assert!(!save.path().exists());
+        save.flush(&[1, 2, 3, 5], 0).unwrap();
+        assert!(save.path().exists());
+        fs::remove_dir_all(rom_path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn flush_ignores_a_changed_timestamp() {
+        let rom_path = temp_rom_path("timestamp");
+        let mut save = SaveFile::for_rom(&rom_path);
+        save.flush(&[1, 2, 0xAA, 0xAA], 2).unwrap();
+        fs::remove_file(save.path()).unwrap();
+
+        save.flush(&[1, 2, 0xBB, 0xBB], 2).unwrap();
+        assert!(!save.path().exists());
+        save.flush(&[1, 3, 0xBB, 0xBB], 2).unwrap();
+        assert_eq!(fs::read(save.path()).unwrap(), [1, 3, 0xBB, 0xBB]);
+        fs::remove_dir_all(rom_path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn autosave_interval() {
+        let mut save = SaveFile::for_rom("game.gb");
+        for _ in 0..AUTOSAVE_INTERVAL / 4096 - 1 {
+            assert!(!save.step(4096));
+        }
+        assert!(save.step(4096));
+    }
+}
@@ -0,0 +1,273 @@
+use std::io::{self, Write};
+
+use crate::memory::Memory;
+use crate::data::HardwareRegister;
+use crate::interrupts::{Interrupt, request_interrupt};
+
+// SC bits
+const SC_TRANSFER: u8 = 0b1000_0000;
+const SC_FAST_CLOCK: u8 = 0b0000_0010; // CGB only
+const SC_INTERNAL_CLOCK: u8 = 0b0000_0001;
+
+// Internal clock is 8192 Hz, or 262144 Hz in CGB fast mode
+const CYCLES_PER_BIT: u32 = 512;
+const FAST_CYCLES_PER_BIT: u32 = 16;
+
+// Anything on the other end of the link port: another Game Boy, a printer, a logger
+pub trait SerialDevice: Send {
+    // The Game Boy drives the clock and is about to shift `outgoing` out. Returns
+    // the byte that shifts back in, or None to hold the transfer until the next step.
+    fn exchange(&mut self, outgoing: u8) -> Option<u8>;
+
+    // Advances the device. A device driving the clock itself returns the byte it
+    // shifted in and takes `outgoing` (SB) in exchange. `waiting` is set while the
+    // Game Boy has a transfer armed with the external clock.
+    fn step(&mut self, _cycles: u16, _outgoing: u8, _waiting: bool) -> Option<u8> {
+        None
+    }
+}
+
+// Prints every byte sent, like the serial output of the blargg test ROMs
+pub struct SerialLogger;
+
+impl SerialDevice for SerialLogger {
+    fn exchange(&mut self, outgoing: u8) -> Option<u8> {
+        print!("{}", outgoing as char);
+        let _ = io::stdout().flush();
+        // Nothing drives the data line, it floats high
+        Some(0xFF)
+    }
+}
+
+// A byte on its way through SB, one bit per serial clock edge
+struct Transfer {
+    incoming: Option<u8>, // None until the device on the other end has answered
+    bits_left: u8,
+    cycles_per_bit: u32,
+    cycles: u32, // cycles into the current bit
+}
+
+impl Transfer {
+    fn new(incoming: Option<u8>, cycles_per_bit: u32) -> Self {
+        Transfer { incoming, bits_left: 8, cycles_per_bit, cycles: 0 }
+    }
+}
+
+pub struct Serial {
+    device: Option<Box<dyn SerialDevice>>,
+    transfer: Option<Transfer>,
+}
+
+impl Serial {
+    pub fn new() -> Self {
+        Serial { device: None, transfer: None }
+    }
+
+    pub fn attach(&mut self, device: Box<dyn SerialDevice>) {
+        self.device = Some(device);
+    }
+
+    pub fn detach(&mut self) -> Option<Box<dyn SerialDevice>> {
+        self.device.take()
+    }
+
+    pub fn step(&mut self, cycles: u16, memory: &mut Memory) {
+        let sc = memory.read_hardware_register(HardwareRegister::SC);
+        let armed = sc & SC_TRANSFER != 0;
+        let internal = sc & SC_INTERNAL_CLOCK != 0;
+        if !armed {
+            self.transfer = None;
+        }
+
+        if armed && internal && self.transfer.is_none() {
+            let fast = memory.model().is_cgb() && sc & SC_FAST_CLOCK != 0;
+            self.transfer = Some(Transfer::new(None, if fast { FAST_CYCLES_PER_BIT } else { CYCLES_PER_BIT }));
+        }
+        if let Some(transfer) = &mut self.transfer
+            && transfer.incoming.is_none()
+        {
+            // The device takes the whole byte up front. With nothing connected
+            // every bit shifted in reads 1.
+            let sb = memory.read_hardware_register(HardwareRegister::SB);
+            transfer.incoming = match &mut self.device {
+                Some(device) => device.exchange(sb),
+                None => Some(0xFF),
+            };
+        }
+        if let Some(transfer) = &mut self.transfer
+            && shift(transfer, cycles as u32, memory)
+        {
+            self.transfer = None;
+        }
+
+        // Runs after the shift so a byte clocked in right as the last one
+        // finished finds SC up to date
+        let sc = memory.read_hardware_register(HardwareRegister::SC);
+        let sb = memory.read_hardware_register(HardwareRegister::SB);
+        let waiting = sc & SC_TRANSFER != 0 && sc & SC_INTERNAL_CLOCK == 0;
+        if let Some(device) = &mut self.device
+            && let Some(incoming) = device.step(cycles, sb, waiting)
+            && waiting
+            && self.transfer.is_none()
+        {
+            // The partner's clock runs at the normal speed
+            self.transfer = Some(Transfer::new(Some(incoming), CYCLES_PER_BIT));
+        }
+    }
+}
+
+// Shifts SB left once per bit period, taking the incoming byte in from the
+// top bit down. Returns true once all 8 bits are through.
+fn shift(transfer: &mut Transfer, cycles: u32, memory: &mut Memory) -> bool {
+    let Some(incoming) = transfer.incoming else {
+        return false;
+    };
+    transfer.cycles += cycles;
+    while transfer.cycles >= transfer.cycles_per_bit && transfer.bits_left > 0 {
+        transfer.cycles -= transfer.cycles_per_bit;
+        transfer.bits_left -= 1;
+        let bit = (incoming >> transfer.bits_left) & 1;
+        let sb = memory.read_hardware_register(HardwareRegister::SB);
+        memory.write_hardware_register(HardwareRegister::SB, (sb << 1) | bit);
+    }
+    if transfer.bits_left > 0 {
+        return false;
+    }
+    let sc = memory.read_hardware_register(HardwareRegister::SC);
+    memory.write_hardware_register(HardwareRegister::SC, sc & !SC_TRANSFER);
+    request_interrupt(memory, Interrupt::Serial);
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    // Answers every byte with a fixed reply and remembers what it was sent
+    struct Echo {
+        reply: u8,
+        sent: Arc<Mutex<Vec<u8>>>,
+    }
+
+    impl SerialDevice for Echo {
+        fn exchange(&mut self, outgoing: u8) -> Option<u8> {
+            self.sent.lock().unwrap().push(outgoing);
+            Some(self.reply)
+        }
+    }
+
+    // Clocks `byte` into the Game Boy on the first step it's waiting
+    struct ExternalClock {
+        byte: Option<u8>,
+    }
+
+    impl SerialDevice for ExternalClock {
+        fn exchange(&mut self, _outgoing: u8) -> Option<u8> {
+            None
+        }
+
+        fn step(&mut self, _cycles: u16, _outgoing: u8, waiting: bool) -> Option<u8> {
+            if waiting { self.byte.take() } else { None }
+        }
+    }
+
+    fn start_transfer(memory: &mut Memory, sb: u8, sc: u8) {
+        memory.write_hardware_register(HardwareRegister::SB, sb);
+        memory.write_hardware_register(HardwareRegister::SC, SC_TRANSFER | sc);
+    }
+
+    fn serial_interrupt(memory: &Memory) -> bool {
+        memory.read_hardware_register(HardwareRegister::IF) & Interrupt::Serial.mask() != 0
+    }
+
+    #[test]
+    fn shifts_one_bit_per_clock() {
+        let mut memory = Memory::new();
+        let mut serial = Serial::new();
+        serial.attach(Box::new(Echo { reply: 0b1010_0110, sent: Arc::default() }));
+        start_transfer(&mut memory, 0b1100_0011, SC_INTERNAL_CLOCK);
+
+        let expected = [0b1000_0111, 0b0000_1110, 0b0001_1101, 0b0011_1010, 0b0111_0100, 0b1110_1001, 0b1101_0011];
+        for sb in expected {
+            serial.step(CYCLES_PER_BIT as u16, &mut memory);
+            assert_eq!(memory.read_hardware_register(HardwareRegister::SB), sb);
+            assert!(memory.read_hardware_register(HardwareRegister::SC) & SC_TRANSFER != 0);
+            assert!(!serial_interrupt(&memory));
+        }
+        serial.step(CYCLES_PER_BIT as u16, &mut memory);
+        assert_eq!(memory.read_hardware_register(HardwareRegister::SB), 0b1010_0110);
+        assert_eq!(memory.read_hardware_register(HardwareRegister::SC) & SC_TRANSFER, 0);
+        assert!(serial_interrupt(&memory));
+    }
+
+    #[test]
+    fn device_sees_the_byte_being_sent() {
+        let mut memory = Memory::new();
+        let mut serial = Serial::new();
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        serial.attach(Box::new(Echo { reply: 0x00, sent: sent.clone() }));
+        for byte in [0x12, 0x34] {
+            start_transfer(&mut memory, byte, SC_INTERNAL_CLOCK);
+            for _ in 0..8 {
+                serial.step(CYCLES_PER_BIT as u16, &mut memory);
+            }
+        }
+        assert_eq!(*sent.lock().unwrap(), [0x12, 0x34]);
+    }
+
+    #[test]
+    fn nothing_connected_reads_ff() {
+        let mut memory = Memory::new();
+        let mut serial = Serial::new();
+        start_transfer(&mut memory, 0x00, SC_INTERNAL_CLOCK);
+        serial.step(4 * CYCLES_PER_BIT as u16, &mut memory);
+        assert_eq!(memory.read_hardware_register(HardwareRegister::SB), 0x0F);
+        serial.step(4 * CYCLES_PER_BIT as u16, &mut memory);
+        assert_eq!(memory.read_hardware_register(HardwareRegister::SB), 0xFF);
+        assert!(serial_interrupt(&memory));
+    }
+
+    #[test]
+    fn cgb_fast_clock() {
+        let mut memory = Memory::new();
+        memory.set_model(crate::model::Model::Cgb);
+        let mut serial = Serial::new();
+        start_transfer(&mut memory, 0x00, SC_INTERNAL_CLOCK | SC_FAST_CLOCK);
+        serial.step(8 * FAST_CYCLES_PER_BIT as u16, &mut memory);
+        assert!(serial_interrupt(&memory));
+    }
+
+    #[test]
+    fn external_clock_waits_for_the_partner() {
+        let mut memory = Memory::new();
+        let mut serial = Serial::new();
+        serial.attach(Box::new(ExternalClock { byte: Some(0xA5) }));
+        start_transfer(&mut memory, 0x00, 0);
+
+        serial.step(4, &mut memory);
+        for _ in 0..4 {
+            serial.step(CYCLES_PER_BIT as u16, &mut memory);
+        }
+        assert_eq!(memory.read_hardware_register(HardwareRegister::SB), 0x0A);
+        assert!(!serial_interrupt(&memory));
+        for _ in 0..4 {
+            serial.step(CYCLES_PER_BIT as u16, &mut memory);
+        }
+        assert_eq!(memory.read_hardware_register(HardwareRegister::SB), 0xA5);
+        assert!(serial_interrupt(&memory));
+    }
+
+    #[test]
+    fn clearing_sc_aborts_the_transfer() {
+        let mut memory = Memory::new();
+        let mut serial = Serial::new();
+        start_transfer(&mut memory, 0x00, SC_INTERNAL_CLOCK);
+        serial.step(4 * CYCLES_PER_BIT as u16, &mut memory);
+        memory.write_hardware_register(HardwareRegister::SC, SC_INTERNAL_CLOCK);
+        serial.step(4 * CYCLES_PER_BIT as u16, &mut memory);
+        assert_eq!(memory.read_hardware_register(HardwareRegister::SB), 0x0F);
+        assert!(!serial_interrupt(&memory));
+    }
+}
@@ -0,0 +1,254 @@
+// Runs whole test ROMs and checks their results the way the common suites
+// report them. Both the mooneye test suite and dmg-acid2 execute LD B,B once
+// they are done; mooneye then leaves the Fibonacci numbers 3, 5, 8, 13, 21, 34
+// in B, C, D, E, H and L on success, dmg-acid2 leaves a frame to compare with
+// its reference screenshot.
+//
+// The in-tree ROMs below are assembled here and always run. The external
+// suites are #[ignore]d since they aren't part of the repository, point
+// RUSTBOY_TEST_ROMS at a directory holding
+//   mooneye/acceptance/ppu/*.gb     (from a mooneye-test-suite build)
+//   dmg-acid2/dmg-acid2.gb
+//   dmg-acid2/reference-dmg.png
+// and run `cargo test -- --ignored`.
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::cartridge::{Cartridge, header_checksum_of};
+use crate::cpu::CPU;
+use crate::png::decode::decode_grayscale;
+use crate::{CYCLES_PER_FRAME, GameBoy, Model, Renderer, SCREEN_HEIGHT, SCREEN_WIDTH};
+
+const LD_B_B: u8 = 0x40;
+const TEST_ROMS_VAR: &str = "RUSTBOY_TEST_ROMS";
+const FIBONACCI: [u8; 6] = [3, 5, 8, 13, 21, 34];
+
+fn boot(rom: Vec<u8>, renderer: Renderer) -> GameBoy {
+    let mut gameboy = GameBoy::new();
+    gameboy.ppu.set_renderer(renderer);
+    gameboy.memory.load_cartridge(Cartridge::from_bytes(rom).expect("test ROM should load"));
+    gameboy.apply_post_boot_state(Model::Dmg);
+    gameboy
+}
+
+// Runs until the CPU is about to execute LD B,B, false if that doesn't happen
+// within `frames` frames
+fn run_to_breakpoint(gameboy: &mut GameBoy, frames: u64) -> bool {
+    let mut cycles = 0;
+    while cycles < frames * CYCLES_PER_FRAME as u64 {
+        if !gameboy.cpu.is_halted && gameboy.memory.read_byte(gameboy.cpu.pc) == LD_B_B {
+            return true;
+        }
+        cycles += gameboy.step() as u64;
+    }
+    false
+}
+
+fn mooneye_passed(cpu: &CPU) -> bool {
+    [cpu.b, cpu.c, cpu.d, cpu.e, cpu.h, cpu.l] == FIBONACCI
+}
+
+// 32 KiB ROM-only cartridge with `program` at 0x0150, the header entry point
+// jumping to it and `handlers` placed at their addresses
+fn build_rom(program: &[u8], handlers: &[(u16, &[u8])]) -> Vec<u8> {
+    let mut rom = vec![0; 0x8000];
+    rom[0x0100..0x0104].copy_from_slice(&[0x00, 0xC3, 0x50, 0x01]); // NOP, JP 0x0150
+    rom[0x0134..0x0138].copy_from_slice(b"TEST");
+    rom[0x014D] = header_checksum_of(&rom);
+    rom[0x0150..0x0150 + program.len()].copy_from_slice(program);
+    for &(address, code) in handlers {
+        rom[address as usize..address as usize + code.len()].copy_from_slice(code);
+    }
+    rom
+}
+
+fn test_rom_dir() -> PathBuf {
+    env::var_os(TEST_ROMS_VAR)
+        .map(PathBuf::from)
+        .unwrap_or_else(|| panic!("set {} to the directory holding the external test ROMs", TEST_ROMS_VAR))
+}
+
+// Reads OAM and VRAM in mode 3, where both read 0xFF, and again in mode 0
+// where they hold what was written with the LCD off
+#[test]
+fn in_tree_rom_checks_ppu_memory_locking() {
+    let program = [
+        0xF3, // DI
+        // wait_vblank:
+        0xF0, 0x44, // LDH A,(LY)
+        0xFE, 0x90, // CP 144
+        0x38, 0xFA, // JR C,wait_vblank
+        0xAF, // XOR A
+        0xE0, 0x40, // LDH (LCDC),A, LCD off
+        0x3E, 0x12, // LD A,0x12
+        0xEA, 0x00, 0xFE, // LD (0xFE00),A
+        0x3E, 0x34, // LD A,0x34
+        0xEA, 0x00, 0x80, // LD (0x8000),A
+        0x3E, 0x91, // LD A,0x91
+        0xE0, 0x40, // LDH (LCDC),A, LCD on
+        // wait_line_1:
+        0xF0, 0x44, // LDH A,(LY)
+        0xFE, 0x01, // CP 1
+        0x20, 0xFA, // JR NZ,wait_line_1
+        // wait_mode_3:
+        0xF0, 0x41, // LDH A,(STAT)
+        0xE6, 0x03, // AND 3
+        0xFE, 0x03, // CP 3
+        0x20, 0xF8, // JR NZ,wait_mode_3
+        0xFA, 0x00, 0xFE, // LD A,(0xFE00)
+        0x47, // LD B,A
+        0xFA, 0x00, 0x80, // LD A,(0x8000)
+        0xA0, // AND B
+        0xFE, 0xFF, // CP 0xFF
+        0x20, 0x21, // JR NZ,fail
+        // wait_mode_0:
+        0xF0, 0x41, // LDH A,(STAT)
+        0xE6, 0x03, // AND 3
+        0x20, 0xFA, // JR NZ,wait_mode_0
+        0xFA, 0x00, 0xFE, // LD A,(0xFE00)
+        0xFE, 0x12, // CP 0x12
+        0x20, 0x14, // JR NZ,fail
+        0xFA, 0x00, 0x80, // LD A,(0x8000)
+        0xFE, 0x34, // CP 0x34
+        0x20, 0x0D, // JR NZ,fail
+        0x06, 0x03, 0x0E, 0x05, 0x16, 0x08, // LD B,3; LD C,5; LD D,8
+        0x1E, 0x0D, 0x26, 0x15, 0x2E, 0x22, // LD E,13; LD H,21; LD L,34
+        0x40, // LD B,B
+        // fail:
+        0x06, 0x42, // LD B,0x42
+        0x40, // LD B,B
+    ];
+    for renderer in [Renderer::Scanline, Renderer::Fifo] {
+        let mut gameboy = boot(build_rom(&program, &[]), renderer);
+        assert!(run_to_breakpoint(&mut gameboy, 10), "{:?} never reached LD B,B", renderer);
+        assert!(mooneye_passed(&gameboy.cpu), "{:?}: {}", renderer, gameboy.cpu);
+    }
+}
+
+// Sets SCX to the next line's LY from the HBlank interrupt, so every line
+// shows the same tile row shifted by its own line number
+#[test]
+fn in_tree_rom_scrolls_every_line_from_hblank() {
+    const LOW: u8 = 0xE4;
+    const HIGH: u8 = 0xC2;
+    let program = [
+        0xF3, // DI
+        // wait_vblank:
+        0xF0, 0x44, // LDH A,(LY)
+        0xFE, 0x90, // CP 144
+        0x38, 0xFA, // JR C,wait_vblank
+        0xAF, // XOR A
+        0xE0, 0x40, // LDH (LCDC),A, LCD off
+        0x21, 0x00, 0x80, // LD HL,0x8000
+        0x06, 0x08, // LD B,8
+        // tile_rows:
+        0x3E, LOW, // LD A,LOW
+        0x22, // LD (HL+),A
+        0x3E, HIGH, // LD A,HIGH
+        0x22, // LD (HL+),A
+        0x05, // DEC B
+        0x20, 0xF7, // JR NZ,tile_rows
+        0x21, 0x00, 0x98, // LD HL,0x9800
+        // clear_map:
+        0xAF, // XOR A
+        0x22, // LD (HL+),A
+        0x7C, // LD A,H
+        0xFE, 0x9C, // CP 0x9C
+        0x20, 0xF9, // JR NZ,clear_map
+        0x3E, 0xE4, // LD A,0xE4
+        0xE0, 0x47, // LDH (BGP),A
+        0xAF, // XOR A
+        0xE0, 0x43, // LDH (SCX),A
+        0x3E, 0x08, // LD A,0x08
+        0xE0, 0x41, // LDH (STAT),A, HBlank interrupt
+        0x3E, 0x02, // LD A,0x02
+        0xE0, 0xFF, // LDH (IE),A
+        0xAF, // XOR A
+        0xE0, 0x0F, // LDH (IF),A
+        0x3E, 0x91, // LD A,0x91
+        0xE0, 0x40, // LDH (LCDC),A, LCD on
+        0xFB, // EI
+        0x0E, 0x02, // LD C,2
+        // next_frame:
+        0xF0, 0x44, // LDH A,(LY)
+        0xFE, 0x90, // CP 144
+        0x28, 0xFA, // JR Z,next_frame
+        // wait_frame:
+        0xF0, 0x44, // LDH A,(LY)
+        0xFE, 0x90, // CP 144
+        0x20, 0xFA, // JR NZ,wait_frame
+        0x0D, // DEC C
+        0x20, 0xF1, // JR NZ,next_frame
+        0x40, // LD B,B
+    ];
+    let stat_handler = [
+        0xF5, // PUSH AF
+        0xF0, 0x44, // LDH A,(LY)
+        0x3C, // INC A
+        0xE0, 0x43, // LDH (SCX),A
+        0xF1, // POP AF
+        0xD9, // RETI
+    ];
+
+    for renderer in [Renderer::Scanline, Renderer::Fifo] {
+        let mut gameboy = boot(build_rom(&program, &[(0x0048, &stat_handler)]), renderer);
+        assert!(run_to_breakpoint(&mut gameboy, 10), "{:?} never reached LD B,B", renderer);
+
+        let frame = gameboy.framebuffer();
+        for y in 0..SCREEN_HEIGHT {
+            // Line 0 keeps the value written after line 143 of the previous frame
+            let scx = if y == 0 { SCREEN_HEIGHT } else { y };
+            for x in 0..SCREEN_WIDTH {
+                let bit = 7 - (x + scx) % 8;
+                let shade = (LOW >> bit) & 1 | ((HIGH >> bit) & 1) << 1;
+                assert_eq!(frame[y * SCREEN_WIDTH + x], shade, "{:?} pixel {},{}", renderer, x, y);
+            }
+        }
+    }
+}
+
+#[test]
+#[ignore = "needs the mooneye test suite, see the top of this file"]
+fn mooneye_ppu_acceptance() {
+    let dir = test_rom_dir().join("mooneye/acceptance/ppu");
+    let mut roms: Vec<PathBuf> = fs::read_dir(&dir)
+        .unwrap_or_else(|err| panic!("can't read {}: {}", dir.display(), err))
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "gb"))
+        .collect();
+    roms.sort();
+    assert!(!roms.is_empty(), "no ROMs in {}", dir.display());
+
+    let mut failures = Vec::new();
+    for path in &roms {
+        let mut gameboy = boot(fs::read(path).unwrap(), Renderer::Fifo);
+        if !run_to_breakpoint(&mut gameboy, 600) || !mooneye_passed(&gameboy.cpu) {
+            failures.push(path.file_name().unwrap().to_string_lossy().into_owned());
+        }
+    }
+    assert!(failures.is_empty(), "{} of {} failed: {}", failures.len(), roms.len(), failures.join(", "));
+}
+
+#[test]
+#[ignore = "needs dmg-acid2, see the top of this file"]
+fn dmg_acid2_matches_the_reference() {
+    let dir = test_rom_dir().join("dmg-acid2");
+    let mut gameboy = boot(fs::read(dir.join("dmg-acid2.gb")).unwrap(), Renderer::Fifo);
+    assert!(run_to_breakpoint(&mut gameboy, 600), "dmg-acid2 never reached LD B,B");
+    // The breakpoint comes right after the test frame is set up, let it show
+    gameboy.run_frame();
+    gameboy.run_frame();
+
+    let (width, height, gray) = decode_grayscale(&fs::read(dir.join("reference-dmg.png")).unwrap()).unwrap();
+    assert_eq!((width as usize, height as usize), (SCREEN_WIDTH, SCREEN_HEIGHT));
+    // Shade 0 is white, the reference uses four evenly spaced grays
+    let expected: Vec<u8> = gray.iter().map(|&gray| 3 - ((gray as u16 + 42) / 85) as u8).collect();
+    let frame = gameboy.framebuffer();
+    let mismatches: Vec<(usize, usize)> = (0..SCREEN_WIDTH * SCREEN_HEIGHT)
+        .filter(|&i| frame[i] != expected[i])
+        .map(|i| (i % SCREEN_WIDTH, i / SCREEN_WIDTH))
+        .collect();
+    assert!(mismatches.is_empty(), "{} pixels differ, first at {:?}", mismatches.len(), mismatches.first());
+}
@@ -20,6 +20,12 @@ impl Timer {
         }
     }
     
+    // Sets the full 16 bit divider, DIV is the upper byte
+    pub fn set_divider(&mut self, divider: u16, memory: &mut Memory) {
+        memory.write_hardware_register(HardwareRegister::DIV, (divider >> 8) as u8);
+        self.div_counter = divider & 0xFF;
+    }
+
     pub fn step(&mut self, cycles: u16, memory: &mut Memory) {
         // DIV (0xFF04) — increments every 256 cycles
         self.div_counter += cycles;
@@ -0,0 +1,145 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+// 16 bit PCM WAV writer. The sizes in the header are filled in by finish(),
+// so the file is only valid once that ran.
+pub struct WavWriter {
+    file: BufWriter<File>,
+    data_bytes: u32,
+}
+
+const HEADER_SIZE: u32 = 44;
+const BITS_PER_SAMPLE: u16 = 16;
+
+impl WavWriter {
+    pub fn create<P: AsRef<Path>>(path: P, channels: u16, sample_rate: u32) -> io::Result<Self> {
+        let block_align = channels
+            .checked_mul(BITS_PER_SAMPLE / 8)
+            .ok_or_else(|| too_large("channel count"))?;
+        let byte_rate = sample_rate
+            .checked_mul(block_align as u32)
+            .ok_or_else(|| too_large("sample rate"))?;
+        let mut file = BufWriter::new(File::create(path)?);
+
+        file.write_all(b"RIFF")?;
+        file.write_all(&0u32.to_le_bytes())?; // patched by finish()
+        file.write_all(b"WAVE")?;
+
+        file.write_all(b"fmt ")?;
+        file.write_all(&16u32.to_le_bytes())?;
+        file.write_all(&1u16.to_le_bytes())?; // PCM
+        file.write_all(&channels.to_le_bytes())?;
+        file.write_all(&sample_rate.to_le_bytes())?;
+        file.write_all(&byte_rate.to_le_bytes())?;
+        file.write_all(&block_align.to_le_bytes())?;
+        file.write_all(&BITS_PER_SAMPLE.to_le_bytes())?;
+
+        file.write_all(b"data")?;
+        file.write_all(&0u32.to_le_bytes())?; // patched by finish()
+
+        Ok(WavWriter { file, data_bytes: 0 })
+    }
+
+    // Samples in -1.0..=1.0, interleaved when there's more than one channel
+    // Fails without writing anything once the data would no longer fit the
+    // 32 bit sizes in the header
+    pub fn write_samples(&mut self, samples: &[f32]) -> io::Result<()> {
+        let data_bytes = u32::try_from(samples.len())
+            .ok()
+            .and_then(|len| len.checked_mul(BITS_PER_SAMPLE as u32 / 8))
+            .and_then(|bytes| bytes.checked_add(self.data_bytes))
+            .filter(|bytes| bytes.checked_add(HEADER_SIZE - 8).is_some())
+            .ok_or_else(|| too_large("audio data"))?;
+        for &sample in samples {
+            self.file.write_all(&sample_to_i16(sample).to_le_bytes())?;
+        }
+        self.data_bytes = data_bytes;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> io::Result<()> {
+        self.file.seek(SeekFrom::Start(4))?;
+        // Can't overflow, write_samples keeps the total within range
+        self.file.write_all(&(HEADER_SIZE - 8 + self.data_bytes).to_le_bytes())?;
+        self.file.seek(SeekFrom::Start(40))?;
+        self.file.write_all(&self.data_bytes.to_le_bytes())?;
+        self.file.flush()
+    }
+}
+
+fn too_large(what: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, format!("{} too large for a WAV file", what))
+}
+
+pub fn sample_to_i16(sample: f32) -> i16 {
+    (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("rustboy-{}-{}.wav", name, std::process::id()))
+    }
+
+    fn le_u32(bytes: &[u8]) -> u32 {
+        u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+    }
+
+    fn le_u16(bytes: &[u8]) -> u16 {
+        u16::from_le_bytes([bytes[0], bytes[1]])
+    }
+
+    #[test]
+    fn header_and_data() {
+        let path = temp_path("header");
+        let mut writer = WavWriter::create(&path, 2, 44100).unwrap();
+        writer.write_samples(&[0.0, 1.0, -1.0, 0.5]).unwrap();
+        writer.write_samples(&[2.0, -2.0]).unwrap();
+        writer.finish().unwrap();
+
+        let file = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(file.len(), HEADER_SIZE as usize + 12);
+        assert_eq!(&file[0..4], b"RIFF");
+        assert_eq!(le_u32(&file[4..]), file.len() as u32 - 8);
+        assert_eq!(&file[8..16], b"WAVEfmt ");
+        assert_eq!(le_u32(&file[16..]), 16);
+        assert_eq!(le_u16(&file[20..]), 1);
+        assert_eq!(le_u16(&file[22..]), 2);
+        assert_eq!(le_u32(&file[24..]), 44100);
+        assert_eq!(le_u32(&file[28..]), 44100 * 4);
+        assert_eq!(le_u16(&file[32..]), 4);
+        assert_eq!(le_u16(&file[34..]), 16);
+        assert_eq!(&file[36..40], b"data");
+        assert_eq!(le_u32(&file[40..]), 12);
+
+        let samples: Vec<i16> = file[44..].chunks(2).map(|pair| i16::from_le_bytes([pair[0], pair[1]])).collect();
+        assert_eq!(samples, [0, i16::MAX, -i16::MAX, i16::MAX / 2, i16::MAX, -i16::MAX]);
+    }
+
+    #[test]
+    fn rejects_rates_that_overflow_the_header() {
+        let path = temp_path("rate");
+        let err = WavWriter::create(&path, 2, u32::MAX / 2).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn rejects_data_past_4_gib() {
+        let path = temp_path("size");
+        let mut writer = WavWriter::create(&path, 1, 44100).unwrap();
+        writer.data_bytes = u32::MAX - (HEADER_SIZE - 8) - 2;
+        let err = writer.write_samples(&[0.0, 0.0]).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(writer.data_bytes, u32::MAX - (HEADER_SIZE - 8) - 2);
+        writer.write_samples(&[]).unwrap();
+        drop(writer);
+        fs::remove_file(&path).unwrap();
+    }
+}